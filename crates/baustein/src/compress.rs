@@ -0,0 +1,427 @@
+/*! Chunks which take less memory than a flat array.
+ *
+ * `CompressedChunk` picks the smallest of the representations when compressed,
+ * and reads through `Space` like any other chunk.
+ * Use it in a `ChunkedWorld` via `CompressedWorld`.
+ *
+ * Voxels must be `Hash + Eq` to build palettes.
+ * Floats can go in wrapped, e.g. in `float_ord::FloatOrd`.
+ */
+use ndshape::ConstShape;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+use crate::indices::{to_u32_arr, ChunkShape, Index};
+use crate::traits::{ChunkSize, CuboidIndices, IterableSpace, MutChunk, Space};
+use crate::world::{get_chunk_dimensions, ChunkedWorld};
+
+
+const VOLUME: usize = <ChunkShape as ConstShape<3>>::SIZE as usize;
+
+fn linearize(offset: Index) -> usize {
+    <ChunkShape as ConstShape<3>>::linearize(to_u32_arr(offset.into())) as usize
+}
+
+/// A world which keeps its chunks compressed.
+pub type CompressedWorld<V> = ChunkedWorld<V, CompressedChunk<V>>;
+
+/// Chunk voxels stored as indices into a palette of the voxels present in the chunk.
+/// Each index takes as few bits as the palette size allows.
+#[derive(Clone, Debug)]
+pub struct PaletteChunk<V> {
+    palette: Vec<V>,
+    /// Palette index of each voxel, for quick setting.
+    lookup: HashMap<V, usize>,
+    bits: u32,
+    /// Indices never cross the boundary of a word.
+    words: Vec<u64>,
+}
+
+/// The lookup follows from the palette.
+impl<V: PartialEq> PartialEq for PaletteChunk<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.palette == other.palette
+            && self.bits == other.bits
+            && self.words == other.words
+    }
+}
+
+impl<V: Copy + Eq + Hash> PaletteChunk<V> {
+    fn new(palette: Vec<V>, indices: impl Iterator<Item=usize>) -> Self {
+        let bits = get_bits(palette.len());
+        let lookup = palette.iter()
+            .enumerate()
+            .map(|(index, v)| (*v, index))
+            .collect();
+        let mut chunk = PaletteChunk {
+            palette,
+            lookup,
+            bits,
+            words: vec![0; get_word_count(bits)],
+        };
+        for (i, index) in indices.enumerate() {
+            chunk.set_index(i, index);
+        }
+        chunk
+    }
+
+    /// Grows the palette if needed.
+    /// Unused palette entries are not removed until recompression.
+    fn set(&mut self, i: usize, value: V) {
+        let index = match self.lookup.get(&value) {
+            Some(index) => *index,
+            None => {
+                self.palette.push(value);
+                if get_bits(self.palette.len()) > self.bits {
+                    let indices: Vec<_> = (0..VOLUME).map(|i| self.get_index(i)).collect();
+                    *self = PaletteChunk::new(mem::take(&mut self.palette), indices.into_iter());
+                } else {
+                    self.lookup.insert(value, self.palette.len() - 1);
+                }
+                self.palette.len() - 1
+            },
+        };
+        self.set_index(i, index);
+    }
+}
+
+impl<V: Copy> PaletteChunk<V> {
+    fn get_index(&self, i: usize) -> usize {
+        let per_word = 64 / self.bits as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[i / per_word] >> shift) & mask) as usize
+    }
+
+    fn set_index(&mut self, i: usize, index: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !(mask << shift)) | ((index as u64) << shift);
+    }
+
+    fn get(&self, i: usize) -> V {
+        self.palette[self.get_index(i)]
+    }
+
+    fn get_size(&self) -> usize {
+        self.palette.len() * mem::size_of::<V>() + self.words.len() * mem::size_of::<u64>()
+    }
+}
+
+/// Collects distinct voxels into a palette,
+/// and returns it along with the palette index of each voxel.
+fn index_palette<V: Copy + Eq + Hash>(voxels: impl Iterator<Item=V>) -> (Vec<V>, Vec<usize>) {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let indices = voxels
+        .map(|v| *lookup.entry(v).or_insert_with(|| {
+            palette.push(v);
+            palette.len() - 1
+        }))
+        .collect();
+    (palette, indices)
+}
+
+/// Bits needed to index the palette.
+fn get_bits(palette_size: usize) -> u32 {
+    let mut bits = 1;
+    while (1 << bits) < palette_size {
+        bits += 1;
+    }
+    bits
+}
+
+fn get_word_count(bits: u32) -> usize {
+    let per_word = 64 / bits as usize;
+    (VOLUME + per_word - 1) / per_word
+}
+
+/// Chunk voxels stored as runs of the same voxel, in the order of linear indices.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RleChunk<V> {
+    /// The linear index just beyond the run, and the voxel in it.
+    runs: Vec<(u16, V)>,
+}
+
+impl<V: Copy + PartialEq> RleChunk<V> {
+    fn new(voxels: impl Iterator<Item=V>) -> Self {
+        let mut runs: Vec<(u16, V)> = Vec::new();
+        for (i, v) in voxels.enumerate() {
+            match runs.last_mut() {
+                Some((end, last)) if *last == v => *end = i as u16 + 1,
+                _ => runs.push((i as u16 + 1, v)),
+            }
+        }
+        RleChunk { runs }
+    }
+
+    fn get(&self, i: usize) -> V {
+        let run = self.runs.partition_point(|(end, _)| (*end as usize) <= i);
+        self.runs[run].1
+    }
+
+    fn iter(&self) -> impl Iterator<Item=V> + '_ {
+        let mut start = 0;
+        self.runs.iter().flat_map(move |(end, v)| {
+            let length = *end as usize - start;
+            start = *end as usize;
+            std::iter::repeat(*v).take(length)
+        })
+    }
+
+    fn get_size(&self) -> usize {
+        self.runs.len() * mem::size_of::<(u16, V)>()
+    }
+}
+
+/// A chunk in whichever form is the smallest.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CompressedChunk<V> {
+    /// All voxels are the same.
+    Uniform(V),
+    Palette(PaletteChunk<V>),
+    Rle(RleChunk<V>),
+    /// Voxels in the order of linear indices, when nothing else is smaller.
+    Raw(Vec<V>),
+}
+
+impl<V: Default> Default for CompressedChunk<V> {
+    fn default() -> Self {
+        CompressedChunk::Uniform(V::default())
+    }
+}
+
+impl<V: Copy + Eq + Hash> CompressedChunk<V> {
+    /// Takes voxels in the order of linear indices within the chunk.
+    fn from_linear(voxels: Vec<V>) -> Self {
+        let (palette, indices) = index_palette(voxels.iter().copied());
+        if palette.len() == 1 {
+            return CompressedChunk::Uniform(palette[0]);
+        }
+        let rle = RleChunk::new(voxels.iter().copied());
+        let bits = get_bits(palette.len());
+        let palette_size = palette.len() * mem::size_of::<V>()
+            + get_word_count(bits) * mem::size_of::<u64>();
+        let raw_size = voxels.len() * mem::size_of::<V>();
+        if raw_size <= rle.get_size() && raw_size <= palette_size {
+            CompressedChunk::Raw(voxels)
+        } else if rle.get_size() < palette_size {
+            CompressedChunk::Rle(rle)
+        } else {
+            CompressedChunk::Palette(PaletteChunk::new(palette, indices.into_iter()))
+        }
+    }
+
+    /// Compresses any chunk-sized space whose indices start at 0.
+    pub fn compress<S: Space<Voxel=V>>(chunk: &S) -> Self {
+        let voxels = (0..VOLUME)
+            .map(|i| {
+                let index = <ChunkShape as ConstShape<3>>::delinearize(i as u32);
+                chunk.get([index[0] as i32, index[1] as i32, index[2] as i32].into())
+            })
+            .collect();
+        Self::from_linear(voxels)
+    }
+
+    /// Picks the smallest form again, e.g. after many edits.
+    pub fn recompress(&mut self) {
+        let voxels = (0..VOLUME).map(|i| self.get_linear(i)).collect();
+        *self = Self::from_linear(voxels);
+    }
+}
+
+impl<V: Copy + PartialEq> CompressedChunk<V> {
+    fn get_linear(&self, i: usize) -> V {
+        match self {
+            CompressedChunk::Uniform(v) => *v,
+            CompressedChunk::Palette(chunk) => chunk.get(i),
+            CompressedChunk::Rle(chunk) => chunk.get(i),
+            CompressedChunk::Raw(voxels) => voxels[i],
+        }
+    }
+}
+
+impl<V: Copy + PartialEq> ChunkSize for CompressedChunk<V> {
+    fn get_size(&self) -> usize {
+        mem::size_of::<Self>() + match self {
+            CompressedChunk::Uniform(_) => 0,
+            CompressedChunk::Palette(chunk) => chunk.get_size(),
+            CompressedChunk::Rle(chunk) => chunk.get_size(),
+            CompressedChunk::Raw(voxels) => voxels.len() * mem::size_of::<V>(),
+        }
+    }
+}
+
+impl<V: Copy + PartialEq> Space for CompressedChunk<V> {
+    type Voxel = V;
+    fn get(&self, offset: Index) -> V {
+        self.get_linear(linearize(offset))
+    }
+}
+
+/// Uniform and RLE chunks turn into palette chunks when changed.
+/// A palette chunk which grows bigger than raw voxels gets recompressed.
+impl<V: Copy + Eq + Hash> MutChunk for CompressedChunk<V> {
+    type Voxel = V;
+    fn set(&mut self, offset: Index, value: V) {
+        let i = linearize(offset);
+        match self {
+            CompressedChunk::Uniform(v) if *v == value => {},
+            CompressedChunk::Uniform(v) => {
+                let mut chunk = PaletteChunk::new(vec![*v], std::iter::empty());
+                chunk.set(i, value);
+                *self = CompressedChunk::Palette(chunk);
+            },
+            CompressedChunk::Palette(chunk) => {
+                chunk.set(i, value);
+                if chunk.get_size() > VOLUME * mem::size_of::<V>() {
+                    self.recompress();
+                }
+            },
+            CompressedChunk::Rle(rle) => {
+                let (palette, indices) = index_palette(rle.iter());
+                let mut chunk = PaletteChunk::new(palette, indices.into_iter());
+                chunk.set(i, value);
+                *self = CompressedChunk::Palette(chunk);
+            },
+            CompressedChunk::Raw(voxels) => voxels[i] = value,
+        }
+    }
+}
+
+impl<V> IterableSpace for CompressedChunk<V> {
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new([0, 0, 0].into(), get_chunk_dimensions())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prefab::{PaletteIdChunk, PaletteVoxel};
+    use crate::world::Cow;
+
+    fn check_same<S: Space<Voxel=PaletteVoxel>>(chunk: &S, compressed: &CompressedChunk<PaletteVoxel>) {
+        for i in compressed.indices() {
+            assert_eq!(chunk.get(i), compressed.get(i), "at {:?}", i);
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let chunk = PaletteIdChunk::default();
+        let compressed = CompressedChunk::compress(&chunk);
+        assert_eq!(compressed, CompressedChunk::Uniform(PaletteVoxel::EMPTY));
+        assert!(compressed.get_size() < 100);
+    }
+
+    #[test]
+    fn rle_layers() {
+        let mut chunk = PaletteIdChunk::default();
+        for i in chunk.indices() {
+            if i.y() < 5 {
+                chunk.set(i, PaletteVoxel(1));
+            }
+        }
+        let compressed = CompressedChunk::compress(&chunk);
+        assert!(matches!(compressed, CompressedChunk::Rle(_)));
+        check_same(&chunk, &compressed);
+    }
+
+    #[test]
+    fn palette_noise() {
+        let mut chunk = PaletteIdChunk::default();
+        for (n, i) in chunk.indices().enumerate() {
+            chunk.set(i, PaletteVoxel((n * 7 % 3) as u8));
+        }
+        let compressed = CompressedChunk::compress(&chunk);
+        assert!(matches!(compressed, CompressedChunk::Palette(_)));
+        // 2 bits per voxel.
+        assert!(compressed.get_size() < 4096 / 3);
+        check_same(&chunk, &compressed);
+    }
+
+    #[test]
+    fn raw_noise() {
+        let voxels: Vec<u16> = (0..VOLUME as u16).map(|n| n.wrapping_mul(40503)).collect();
+        let compressed = CompressedChunk::from_linear(voxels.clone());
+        assert!(matches!(compressed, CompressedChunk::Raw(_)));
+        assert!(compressed.get_size() <= mem::size_of::<CompressedChunk<u16>>() + VOLUME * 2);
+        for (i, v) in voxels.iter().enumerate() {
+            assert_eq!(compressed.get_linear(i), *v);
+        }
+    }
+
+    #[test]
+    fn set_grows_palette() {
+        let mut chunk = PaletteIdChunk::default();
+        let mut compressed = CompressedChunk::compress(&chunk);
+        for (n, i) in chunk.indices().enumerate().step_by(13) {
+            let v = PaletteVoxel((n % 5) as u8);
+            chunk.set(i, v);
+            compressed.set(i, v);
+        }
+        check_same(&chunk, &compressed);
+        compressed.recompress();
+        check_same(&chunk, &compressed);
+    }
+
+    #[test]
+    fn overwrites_stay_smaller_than_raw() {
+        let mut compressed = CompressedChunk::<u8>::default();
+        for n in 0..=255 {
+            compressed.set([0, 0, 0].into(), n);
+        }
+        assert_eq!(compressed.get([0, 0, 0].into()), 255);
+        assert_eq!(compressed.get([1, 0, 0].into()), 0);
+        assert!(compressed.get_size() <= mem::size_of::<CompressedChunk<u8>>() + VOLUME);
+
+        for (n, i) in compressed.indices().enumerate() {
+            compressed.set(i, n as u8);
+        }
+        assert!(matches!(compressed, CompressedChunk::Raw(_)));
+        for (n, i) in compressed.indices().enumerate() {
+            assert_eq!(compressed.get(i), n as u8);
+        }
+    }
+
+    #[test]
+    fn compress_world() {
+        let mut world = crate::prefab::World::default();
+        let mut cow = Cow::new(&world);
+        for x in 0..64 {
+            cow.set([x, 0, 0].into(), PaletteVoxel(1));
+        }
+        cow.into_changes().apply(&mut world);
+        let compressed = world.compress();
+        let size: usize = compressed.iter_chunks()
+            .map(|(_, chunk)| chunk.get_size())
+            .sum();
+        assert!(size < 4 * 4096 / 4);
+        for x in -1..65 {
+            assert_eq!(compressed.get([x, 0, 0].into()), world.get([x, 0, 0].into()));
+        }
+    }
+
+    #[test]
+    fn overlay_size() {
+        let world = CompressedWorld::<PaletteVoxel>::new();
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(1));
+        // A single voxel in a chunk doesn't take a whole array.
+        assert!(cow.into_changes().get_size() < 4096 / 4);
+    }
+
+    #[test]
+    fn world() {
+        let mut world = CompressedWorld::<PaletteVoxel>::new();
+        let mut cow = Cow::new(&mut world);
+        cow.set([1, -2, 3].into(), PaletteVoxel(4));
+        cow.commit();
+        assert_eq!(world.get([1, -2, 3].into()), PaletteVoxel(4));
+        assert_eq!(world.get([1, -3, 3].into()), PaletteVoxel::EMPTY);
+    }
+}
@@ -0,0 +1,677 @@
+/*! Lazy adaptors which move spaces around.
+ *
+ * None of them copy voxels. Collect into a `FlatPaddedCuboid` to keep the result.
+ * `resample` does that for arbitrary affine transforms.
+ */
+use feldspar_map::units::VoxelUnits;
+use crate::indices::Index;
+use crate::traits::{Cuboid, CuboidIndices, IterableSpace, ShiftedIndices, Space};
+use crate::world::FlatPaddedCuboid;
+
+
+/// The same space, moved by `shift`.
+pub struct Translate<S> {
+    pub(crate) space: S,
+    pub(crate) shift: [i32; 3],
+}
+
+impl<S: Space> Space for Translate<S> {
+    type Voxel = S::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        self.space.get(offset - VoxelUnits(self.shift.into()))
+    }
+}
+
+impl<S: IterableSpace> IterableSpace for Translate<S> {
+    type Indices = ShiftedIndices<S::Indices>;
+    fn indices(&self) -> Self::Indices {
+        ShiftedIndices::new(self.space.indices(), self.shift)
+    }
+}
+
+impl<S: Cuboid> Cuboid for Translate<S> {
+    fn get_offset(&self) -> Index {
+        self.space.get_offset() + VoxelUnits(self.shift.into())
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        self.space.get_dimensions()
+    }
+}
+
+/// Only the voxels inside the extent. Outside, default voxels.
+/// The extent doesn't have to lie inside the original space.
+pub struct Crop<S> {
+    pub(crate) space: S,
+    pub(crate) offset: Index,
+    pub(crate) dimensions: [usize; 3],
+}
+
+impl<S> Crop<S> {
+    fn contains(&self, index: Index) -> bool {
+        let end = self.get_beyond_opposite_corner();
+        (0..3).all(|i| self.offset[i] <= index[i] && index[i] < end[i])
+    }
+}
+
+impl<S> Space for Crop<S>
+    where
+    S: Space,
+    S::Voxel: Default,
+{
+    type Voxel = S::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        if self.contains(offset) {
+            self.space.get(offset)
+        } else {
+            Default::default()
+        }
+    }
+}
+
+impl<S> IterableSpace for Crop<S> {
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new(self.offset, self.dimensions)
+    }
+}
+
+impl<S> Cuboid for Crop<S> {
+    fn get_offset(&self) -> Index {
+        self.offset
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+}
+
+/// The space with a margin of default voxels around it.
+pub struct Pad<S> {
+    pub(crate) crop: Crop<S>,
+}
+
+impl<S> Pad<S> {
+    /// `low` is added before the offset, `high` beyond the opposite corner.
+    pub(crate) fn new(space: S, low: [usize; 3], high: [usize; 3]) -> Self
+        where S: Cuboid
+    {
+        let offset = space.get_offset();
+        let dimensions = space.get_dimensions();
+        Self {
+            crop: Crop {
+                offset: [
+                    offset.x() - low[0] as i32,
+                    offset.y() - low[1] as i32,
+                    offset.z() - low[2] as i32,
+                ].into(),
+                dimensions: [
+                    dimensions[0] + low[0] + high[0],
+                    dimensions[1] + low[1] + high[1],
+                    dimensions[2] + low[2] + high[2],
+                ],
+                space,
+            },
+        }
+    }
+}
+
+impl<S> Space for Pad<S>
+    where
+    S: Space + Cuboid,
+    S::Voxel: Default,
+{
+    type Voxel = S::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        // The original space may give something else than default outside.
+        let inner = &self.crop.space;
+        let end = inner.get_beyond_opposite_corner();
+        let start = inner.get_offset();
+        if (0..3).all(|i| start[i] <= offset[i] && offset[i] < end[i]) {
+            inner.get(offset)
+        } else {
+            Default::default()
+        }
+    }
+}
+
+impl<S> IterableSpace for Pad<S> {
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        self.crop.indices()
+    }
+}
+
+impl<S> Cuboid for Pad<S> {
+    fn get_offset(&self) -> Index {
+        self.crop.get_offset()
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        self.crop.get_dimensions()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Axis {
+    X = 0,
+    Y = 1,
+    Z = 2,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+}
+
+/// One of the 48 ways to put a cuboid back on the grid:
+/// axes get swapped around and flipped.
+/// The 24 which don't mirror are the rotations.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Symmetry {
+    /// Which source axis becomes each axis.
+    axes: [Axis; 3],
+    /// Whether each axis is reversed, after swapping.
+    flips: [bool; 3],
+}
+
+impl Symmetry {
+    pub const IDENTITY: Symmetry = Symmetry {
+        axes: [Axis::X, Axis::Y, Axis::Z],
+        flips: [false, false, false],
+    };
+
+    /// Reverses one axis.
+    pub fn mirror(axis: Axis) -> Self {
+        let mut flips = [false; 3];
+        flips[axis as usize] = true;
+        Self { flips, ..Self::IDENTITY }
+    }
+
+    /// A quarter turn around the axis, counter-clockwise when looking from its positive end.
+    pub fn quarter_turn(axis: Axis) -> Self {
+        let (a, b) = match axis {
+            Axis::X => (Axis::Y, Axis::Z),
+            Axis::Y => (Axis::Z, Axis::X),
+            Axis::Z => (Axis::X, Axis::Y),
+        };
+        // a -> b, b -> -a
+        let mut axes = Self::IDENTITY.axes;
+        let mut flips = [false; 3];
+        axes[b as usize] = a;
+        axes[a as usize] = b;
+        flips[a as usize] = true;
+        Self { axes, flips }
+    }
+
+    /// First `self`, then `other`.
+    pub fn then(self, other: Symmetry) -> Self {
+        let mut axes = [Axis::X; 3];
+        let mut flips = [false; 3];
+        for i in 0..3 {
+            let source = other.axes[i] as usize;
+            axes[i] = self.axes[source];
+            flips[i] = self.flips[source] != other.flips[i];
+        }
+        Self { axes, flips }
+    }
+
+    pub fn inverse(self) -> Self {
+        let mut axes = [Axis::X; 3];
+        let mut flips = [false; 3];
+        for i in 0..3 {
+            let source = self.axes[i] as usize;
+            axes[source] = Axis::ALL[i];
+            flips[source] = self.flips[i];
+        }
+        Self { axes, flips }
+    }
+
+    /// False for mirror images.
+    pub fn is_rotation(&self) -> bool {
+        let [a, b, c] = self.axes.map(|a| a as usize);
+        // Even permutations keep handedness, and so does an even number of flips.
+        let even_permutation = (a + 1) % 3 == b && (b + 1) % 3 == c;
+        let even_flips = self.flips.iter().filter(|f| **f).count() % 2 == 0;
+        even_permutation == even_flips
+    }
+
+    /// All 48, starting with the identity.
+    pub fn all() -> impl Iterator<Item=Symmetry> {
+        const PERMUTATIONS: [[Axis; 3]; 6] = [
+            [Axis::X, Axis::Y, Axis::Z],
+            [Axis::X, Axis::Z, Axis::Y],
+            [Axis::Y, Axis::X, Axis::Z],
+            [Axis::Y, Axis::Z, Axis::X],
+            [Axis::Z, Axis::X, Axis::Y],
+            [Axis::Z, Axis::Y, Axis::X],
+        ];
+        PERMUTATIONS.into_iter().flat_map(|axes| {
+            (0..8).map(move |bits| Symmetry {
+                axes,
+                flips: [bits & 1 != 0, bits & 2 != 0, bits & 4 != 0],
+            })
+        })
+    }
+
+    /// The 24 rotations, starting with the identity.
+    pub fn rotations() -> impl Iterator<Item=Symmetry> {
+        Self::all().filter(Symmetry::is_rotation)
+    }
+
+    /// Dimensions of a cuboid after applying.
+    pub fn apply_dimensions(&self, dimensions: [usize; 3]) -> [usize; 3] {
+        self.axes.map(|axis| dimensions[axis as usize])
+    }
+
+    /// Where the voxel goes, relative to the corner of a cuboid with the `dimensions`.
+    fn apply_local(&self, local: [i32; 3], dimensions: [usize; 3]) -> [i32; 3] {
+        let mut out = [0; 3];
+        for i in 0..3 {
+            let source = self.axes[i] as usize;
+            out[i] = if self.flips[i] {
+                dimensions[source] as i32 - 1 - local[source]
+            } else {
+                local[source]
+            };
+        }
+        out
+    }
+}
+
+/// Rotated or mirrored space, still starting at the same corner.
+pub struct Reoriented<S> {
+    pub(crate) space: S,
+    pub(crate) symmetry: Symmetry,
+    /// The inverse of `symmetry`, precalculated.
+    inverse: Symmetry,
+    /// Of the original space.
+    offset: Index,
+    /// Of the original space.
+    dimensions: [usize; 3],
+}
+
+impl<S: Cuboid> Reoriented<S> {
+    pub(crate) fn new(space: S, symmetry: Symmetry) -> Self {
+        Self {
+            offset: space.get_offset(),
+            dimensions: space.get_dimensions(),
+            space,
+            symmetry,
+            inverse: symmetry.inverse(),
+        }
+    }
+}
+
+impl<S> Reoriented<S> {
+    fn to_source(&self, index: Index) -> Index {
+        let local: [i32; 3] = (index - VoxelUnits(self.offset.0)).into();
+        let dimensions = self.symmetry.apply_dimensions(self.dimensions);
+        let source = self.inverse.apply_local(local, dimensions);
+        self.offset + VoxelUnits(source.into())
+    }
+}
+
+impl<S: Space> Space for Reoriented<S> {
+    type Voxel = S::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        self.space.get(self.to_source(offset))
+    }
+}
+
+/// Goes over the indices of the original space, moved to where they end up.
+pub struct ReorientedIndices<I> {
+    indices: I,
+    symmetry: Symmetry,
+    offset: Index,
+    dimensions: [usize; 3],
+}
+
+impl<I: Iterator<Item=Index>> Iterator for ReorientedIndices<I> {
+    type Item = Index;
+    fn next(&mut self) -> Option<Index> {
+        self.indices.next().map(|index| {
+            let local: [i32; 3] = (index - VoxelUnits(self.offset.0)).into();
+            let moved = self.symmetry.apply_local(local, self.dimensions);
+            self.offset + VoxelUnits(moved.into())
+        })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl<S: IterableSpace> IterableSpace for Reoriented<S> {
+    type Indices = ReorientedIndices<S::Indices>;
+    fn indices(&self) -> Self::Indices {
+        ReorientedIndices {
+            indices: self.space.indices(),
+            symmetry: self.symmetry,
+            offset: self.offset,
+            dimensions: self.dimensions,
+        }
+    }
+}
+
+impl<S> Cuboid for Reoriented<S> {
+    fn get_offset(&self) -> Index {
+        self.offset
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        self.symmetry.apply_dimensions(self.dimensions)
+    }
+}
+
+/// Maps points as `matrix * point + translation`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Affine {
+    /// Rows.
+    pub matrix: [[f32; 3]; 3],
+    pub translation: [f32; 3],
+}
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine {
+        matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        translation: [0.0, 0.0, 0.0],
+    };
+
+    pub fn apply(&self, point: [f32; 3]) -> [f32; 3] {
+        let mut out = self.translation;
+        for i in 0..3 {
+            for j in 0..3 {
+                out[i] += self.matrix[i][j] * point[j];
+            }
+        }
+        out
+    }
+
+    /// None if the transform squashes space flat.
+    pub fn inverse(&self) -> Option<Self> {
+        let cross = |a: [f32; 3], b: [f32; 3]| [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ];
+        let [r0, r1, r2] = self.matrix;
+        // Columns of the inverse, times the determinant.
+        let columns = [cross(r1, r2), cross(r2, r0), cross(r0, r1)];
+        let det: f32 = (0..3).map(|i| r0[i] * columns[0][i]).sum();
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let mut matrix = [[0.0; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                matrix[i][j] = columns[j][i] / det;
+            }
+        }
+        let partial = Affine { matrix, translation: [0.0; 3] };
+        let moved = partial.apply(self.translation);
+        Some(Affine {
+            matrix,
+            translation: moved.map(|v| -v),
+        })
+    }
+
+    /// The smallest cuboid of whole voxels containing the transformed cuboid.
+    fn get_bounds(&self, offset: Index, dimensions: [usize; 3]) -> (Index, [usize; 3]) {
+        // Rotations land on whole numbers, give or take rounding errors.
+        const SLACK: f32 = 1e-3;
+        let start: [i32; 3] = offset.into();
+        let mut low = [f32::INFINITY; 3];
+        let mut high = [f32::NEG_INFINITY; 3];
+        for corner in 0..8 {
+            let mut point = [0.0; 3];
+            for i in 0..3 {
+                let far = corner & (1 << i) != 0;
+                point[i] = (start[i] + if far { dimensions[i] as i32 } else { 0 }) as f32;
+            }
+            let point = self.apply(point);
+            for i in 0..3 {
+                low[i] = low[i].min(point[i]);
+                high[i] = high[i].max(point[i]);
+            }
+        }
+        let low = low.map(|v| (v + SLACK).floor() as i32);
+        let high = high.map(|v| (v - SLACK).ceil() as i32);
+        let size = |i: usize| (high[i] - low[i]).max(0) as usize;
+        (low.into(), [size(0), size(1), size(2)])
+    }
+}
+
+#[cfg(feature="prefab_bevy")]
+impl From<bevy::transform::components::Transform> for Affine {
+    fn from(transform: bevy::transform::components::Transform) -> Self {
+        use bevy::math::Vec3;
+        let origin = transform.mul_vec3(Vec3::ZERO);
+        let x = transform.mul_vec3(Vec3::new(1.0, 0.0, 0.0)) - origin;
+        let y = transform.mul_vec3(Vec3::new(0.0, 1.0, 0.0)) - origin;
+        let z = transform.mul_vec3(Vec3::new(0.0, 0.0, 1.0)) - origin;
+        Affine {
+            matrix: [
+                [x.x, y.x, z.x],
+                [x.y, y.y, z.y],
+                [x.z, y.z, z.z],
+            ],
+            translation: [origin.x, origin.y, origin.z],
+        }
+    }
+}
+
+/// The space transformed, covering the box around the result.
+/// Each voxel takes the source voxel nearest to where it came from,
+/// so that there are no holes.
+/// A voxel spans from its index to the index + 1.
+pub struct Resampled<S> {
+    space: S,
+    /// Into the source. None when the transform squashes space flat.
+    inverse: Option<Affine>,
+    /// Of the source
+    source: (Index, [usize; 3]),
+    /// Of the result
+    bounds: (Index, [usize; 3]),
+}
+
+impl<S: Cuboid> Resampled<S> {
+    pub fn new(space: S, transform: &Affine) -> Self {
+        let source = (space.get_offset(), space.get_dimensions());
+        let inverse = transform.inverse();
+        let bounds = match inverse {
+            Some(_) => transform.get_bounds(source.0, source.1),
+            None => (source.0, [0, 0, 0]),
+        };
+        Self {
+            space,
+            inverse,
+            source,
+            bounds,
+        }
+    }
+}
+
+impl<S> Space for Resampled<S>
+    where
+    S: Space,
+    S::Voxel: Default,
+{
+    type Voxel = S::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        let inverse = match &self.inverse {
+            Some(inverse) => inverse,
+            None => return Default::default(),
+        };
+        let center = [
+            offset.x() as f32 + 0.5,
+            offset.y() as f32 + 0.5,
+            offset.z() as f32 + 0.5,
+        ];
+        let source = inverse.apply(center).map(|v| v.floor() as i32);
+        let (start, dimensions) = self.source;
+        let inside = (0..3).all(|i| {
+            start[i] <= source[i] && source[i] < start[i] + dimensions[i] as i32
+        });
+        if inside {
+            self.space.get(source.into())
+        } else {
+            Default::default()
+        }
+    }
+}
+
+impl<S> IterableSpace for Resampled<S> {
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new(self.bounds.0, self.bounds.1)
+    }
+}
+
+impl<S> Cuboid for Resampled<S> {
+    fn get_offset(&self) -> Index {
+        self.bounds.0
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        self.bounds.1
+    }
+}
+
+/// Transforms the space, sampling the nearest source voxels.
+pub fn resample<S>(source: &S, transform: &Affine) -> FlatPaddedCuboid<S::Voxel>
+    where
+    S: Space + Cuboid,
+    S::Voxel: Default,
+{
+    Resampled::new(source, transform).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Each voxel knows where it started.
+    fn numbered() -> FlatPaddedCuboid<[i32; 3]> {
+        let empty = FlatPaddedCuboid::<[i32; 3]>::new([1, 2, 3].into(), [2, 3, 4]);
+        empty.map_index(|i, _| -> [i32; 3] { i.into() }).into()
+    }
+
+    #[test]
+    fn translate() {
+        let space = numbered();
+        let moved = space.translate([10, 0, -1]);
+        assert_eq!(moved.get_offset(), [11, 2, 2].into());
+        assert_eq!(moved.get([11, 2, 2].into()), [1, 2, 3]);
+        let first = moved.indices().next().unwrap();
+        assert_eq!(first, [11, 2, 2].into());
+    }
+
+    #[test]
+    fn crop_and_pad() {
+        let space = numbered();
+        let cropped = space.crop([2, 2, 3].into(), [4, 1, 1]);
+        assert_eq!(cropped.indices().count(), 4);
+        assert_eq!(cropped.get([2, 2, 3].into()), [2, 2, 3]);
+        assert_eq!(cropped.get([1, 2, 3].into()), [0, 0, 0]);
+
+        let padded = space.pad([1, 0, 0], [0, 0, 2]);
+        assert_eq!(padded.get_offset(), [0, 2, 3].into());
+        assert_eq!(padded.get_dimensions(), [3, 3, 6]);
+        assert_eq!(padded.get([1, 2, 3].into()), [1, 2, 3]);
+        assert_eq!(padded.voxels().filter(|(_, v)| *v == [0, 0, 0]).count(), 3 * 3 * 6 - 2 * 3 * 4);
+    }
+
+    #[test]
+    fn symmetries() {
+        assert_eq!(Symmetry::all().collect::<HashSet<_>>().len(), 48);
+        assert_eq!(Symmetry::rotations().count(), 24);
+        assert!(!Symmetry::mirror(Axis::Y).is_rotation());
+        for axis in Axis::ALL {
+            let turn = Symmetry::quarter_turn(axis);
+            assert!(turn.is_rotation());
+            let full = turn.then(turn).then(turn).then(turn);
+            assert_eq!(full, Symmetry::IDENTITY);
+        }
+        for symmetry in Symmetry::all() {
+            assert_eq!(symmetry.then(symmetry.inverse()), Symmetry::IDENTITY);
+        }
+    }
+
+    #[test]
+    fn rotate() {
+        let space = numbered();
+        // x becomes y, y becomes -x
+        let turned = space.reorient(Symmetry::quarter_turn(Axis::Z));
+        assert_eq!(turned.get_offset(), [1, 2, 3].into());
+        assert_eq!(turned.get_dimensions(), [3, 2, 4]);
+        // Lowest x, y in the source ends up at highest x, lowest y.
+        assert_eq!(turned.get([3, 2, 3].into()), [1, 2, 3]);
+        // Every voxel shows up once, and where it claims to be.
+        let mut seen = HashSet::new();
+        for (index, voxel) in turned.voxels() {
+            assert_eq!(turned.get(index), voxel);
+            seen.insert(voxel);
+        }
+        assert_eq!(seen.len(), 2 * 3 * 4);
+    }
+
+    #[test]
+    fn mirror() {
+        let space = numbered();
+        let mirrored = space.mirror(Axis::X);
+        assert_eq!(mirrored.get([1, 2, 3].into()), [2, 2, 3]);
+        assert_eq!(mirrored.get([2, 4, 6].into()), [1, 4, 6]);
+        let back = mirrored.mirror(Axis::X);
+        assert_eq!(back.get([1, 2, 3].into()), [1, 2, 3]);
+    }
+
+    #[test]
+    fn affine_inverse() {
+        let transform = Affine {
+            matrix: [[0.0, -2.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [3.0, 0.0, -1.0],
+        };
+        let inverse = transform.inverse().unwrap();
+        let point = [1.0, 2.0, 3.0];
+        let back = inverse.apply(transform.apply(point));
+        for i in 0..3 {
+            assert!((back[i] - point[i]).abs() < 1e-5);
+        }
+        let flat = Affine {
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]],
+            ..Affine::IDENTITY
+        };
+        assert_eq!(flat.inverse(), None);
+    }
+
+    #[test]
+    fn resample_turn() {
+        let space = numbered();
+        // A quarter turn around z, through the origin.
+        let transform = Affine {
+            matrix: [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0, 0.0, 0.0],
+        };
+        let turned = resample(&space, &transform);
+        // x in 1..3, y in 2..5 turn into x in -5..-2, y in 1..3
+        assert_eq!(turned.get_offset(), [-5, 1, 3].into());
+        assert_eq!(turned.get_dimensions(), [3, 2, 4]);
+        // The voxel spanning x 1..2, y 2..3 ends up spanning x -3..-2, y 1..2.
+        assert_eq!(turned.get([-3, 1, 3].into()), [1, 2, 3]);
+        let distinct: HashSet<_> = turned.voxels().map(|(_, v)| v).collect();
+        assert_eq!(distinct.len(), 2 * 3 * 4);
+    }
+
+    #[test]
+    fn resample_without_holes() {
+        let empty = FlatPaddedCuboid::<u8>::new([0, 0, 0].into(), [8, 8, 1]);
+        let solid: FlatPaddedCuboid<u8> = empty.map(|_| 1u8).into();
+        // An eighth of a turn around z.
+        let (sin, cos) = std::f32::consts::FRAC_PI_4.sin_cos();
+        let transform = Affine {
+            matrix: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0, 0.0, 0.0],
+        };
+        let turned = resample(&solid, &transform);
+        // Along the middle of the turned square, every voxel is filled.
+        let middle = turned.get_offset().x() + turned.get_dimensions()[0] as i32 / 2;
+        for y in 1..11 {
+            assert_eq!(turned.get([middle, y, 0].into()), 1, "hole at y={}", y);
+        }
+    }
+}
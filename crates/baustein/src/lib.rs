@@ -1,3 +1,5 @@
+pub mod compress;
+pub mod geometry;
 pub mod indices;
 pub mod prefab;
 pub mod re;
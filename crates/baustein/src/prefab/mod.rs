@@ -7,12 +7,12 @@ use block_mesh::{ MergeVoxel, Voxel };
 use ndshape::ConstPow2Shape3u32;
 use ndshape::ConstPow2Shape3usize;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
 use std::fmt;
 
-use crate::indices::{ to_i32_arr, to_u32_arr, usize_to_i32_arr, ChunkIndex, Index, WorldIndex };
-use crate::traits::{ IterableSpace, MutChunk, Space };
-use crate::world::Cow;
+use crate::indices::{ to_u32_arr, Index };
+use crate::traits::{ ChunkSize, CuboidIndices, IterableSpace, MutChunk, Space };
+use crate::compress::{CompressedChunk, CompressedWorld};
+use crate::world::{get_chunk_dimensions, ChunkedWorld, ChunkStoreMut};
 
 // traits
 use ndshape::ConstShape;
@@ -22,7 +22,7 @@ pub type ChunkShape = ConstPow2Shape3u32<4, 4, 4>;
 
 
 /// The voxel that maps to a palette entry.
-#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct PaletteVoxel(pub u8);
 
 impl PaletteVoxel {
@@ -60,7 +60,7 @@ impl PaletteIdChunk {
 
 impl Default for PaletteIdChunk {
     fn default() -> Self {
-        Self([PaletteVoxel::EMPTY; 4096])
+        Self::EMPTY
     }
 }
 
@@ -81,11 +81,15 @@ impl MutChunk for PaletteIdChunk {
 }
 
 impl IterableSpace for PaletteIdChunk {
-    fn visit_indices<F: FnMut(Index)>(&self, mut f: F) {
-        (0..ChunkShape::SIZE)
-            .map(|i| <ChunkShape as ConstShape<3>>::delinearize(i))
-            .map(|index| to_i32_arr(index).into())
-            .for_each(f);
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new([0, 0, 0].into(), get_chunk_dimensions())
+    }
+}
+
+impl ChunkSize for PaletteIdChunk {
+    fn get_size(&self) -> usize {
+        std::mem::size_of::<Self>()
     }
 }
 
@@ -104,12 +108,7 @@ impl<T> IterableSpace for T where T: ConstShape<3, Coord=u32> {
 /// A really terrible, simple world type
 /// What do I want from the world?
 /// Definitely not direct mutability. Use the Cow.
-#[derive(Default)]
-pub struct World {
-    // This is public because of Cow being coupled with World.
-    // New interfaces to decouple might be needed.
-    pub(crate) chunks: HashMap<ChunkIndex, PaletteIdChunk>,
-}
+pub type World = ChunkedWorld<PaletteVoxel, PaletteIdChunk>;
 
 /// This is really slow, we already know chunk coords are pow2.
 fn trunc(v: i32, thr: i32) -> i32 {
@@ -118,44 +117,20 @@ fn trunc(v: i32, thr: i32) -> i32 {
 }
 
 impl World {
-    pub fn get_chunk(&self, offset: ChunkIndex) -> PaletteIdChunk {
-        *self.chunks.get(&offset).clone().unwrap_or(&PaletteIdChunk::EMPTY)
-    }
-    
-    pub fn get_chunk_ref(&self, offset: ChunkIndex) -> &PaletteIdChunk {
-        self.chunks.get(&offset).clone().unwrap_or(&PaletteIdChunk::EMPTY)
-    }
-
-    pub fn iter_chunks(&self) -> impl Iterator<Item=(ChunkIndex, &PaletteIdChunk)> {
-        self.chunks.iter().map(|(offset, chunk)| (offset.clone(), chunk))
-    }
-
-    pub fn iter_chunk_indices<'a>(&'a self) -> impl Iterator<Item=ChunkIndex> + 'a {
-        self.chunks.keys().cloned()
-    }
-
-    fn cow<'a>(&'a self) -> Cow<'a> {
-        Cow::new(&self)
-    }
-}
-
-impl Space for World {
-    type Voxel = PaletteVoxel;
-    fn get(&self, offset: WorldIndex) -> Self::Voxel {
-        let ci = ChunkIndex::new_encompassing(offset);
-        match self.chunks.get(&ci) {
-            Some(chunk) => chunk.get(Index::new(ci.get_internal_offset(offset))),
-            None => Default::default(),
+    /// Copies into a world which takes less memory, especially when mostly empty.
+    pub fn compress(&self) -> CompressedWorld<PaletteVoxel> {
+        let mut compressed = CompressedWorld::new();
+        for (offset, chunk) in self.iter_chunks() {
+            compressed.insert_chunk(offset, CompressedChunk::compress(chunk));
         }
+        compressed
     }
 }
 
 impl<const X: usize, const Y: usize, const Z: usize> IterableSpace for ConstPow2Shape3usize<X, Y, Z> {
-    fn visit_indices<F: FnMut(Index)>(&self, f: F) {
-        (0..Self::SIZE)
-            .map(|i| Self::delinearize(i))
-            .map(|index| usize_to_i32_arr(index).into())
-            .for_each(f);
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new([0, 0, 0].into(), Self::ARRAY)
     }
 }
 
@@ -163,6 +138,7 @@ impl<const X: usize, const Y: usize, const Z: usize> IterableSpace for ConstPow2
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::world::Cow;
     use maplit::hashset;
     use std::collections::HashSet;
 
@@ -186,4 +162,17 @@ mod test {
             ),
         );
     }
+
+    #[test]
+    fn world_voxels() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([3, -20, 40].into(), PaletteVoxel(2));
+        cow.into_changes().apply(&mut world);
+        assert_eq!(world.indices().count(), 4096);
+        let solid: Vec<_> = world.voxels()
+            .filter(|(_, v)| *v != PaletteVoxel::EMPTY)
+            .collect();
+        assert_eq!(solid, vec![([3, -20, 40].into(), PaletteVoxel(2))]);
+    }
 }
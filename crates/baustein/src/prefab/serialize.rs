@@ -2,8 +2,12 @@
 use serde;
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
+use std::collections::HashMap;
+
+use crate::indices::{ChunkIndex, Index};
 use crate::re::ConstShape;
-use crate::world::{FlatPaddedCuboid, FlatPaddedGridCuboid};
+use crate::world::{get_chunk_dimensions, ChunkStoreMut, DirtyChunks, FlatPaddedCuboid, FlatPaddedGridCuboid, Overlay};
+use super::{PaletteIdChunk, PaletteVoxel, World};
 
 /// Helper for serialization without relying on random types.
 #[derive(Serialize, Deserialize)]
@@ -40,3 +44,192 @@ impl<'de, V: Deserialize<'de>, Shape: ConstShape> Deserialize<'de> for FlatPadde
         Ok(unsafe { Self::new_from_samples(ret.data, offset) })
     }
 }
+
+/// Helper for chunk-keyed layouts.
+#[derive(Serialize, Deserialize)]
+struct ChunkEntry<C> {
+    offset: [i64; 3],
+    chunk: C,
+}
+
+fn to_chunk_index<E: serde::de::Error>(offset: [i64; 3]) -> Result<ChunkIndex, E> {
+    let offset: Index = offset.into();
+    let index = ChunkIndex::new_encompassing(offset);
+    if index.get_world_offset() == offset {
+        Ok(index)
+    } else {
+        Err(E::custom("Chunk offset not aligned to chunk size"))
+    }
+}
+
+impl Serialize for PaletteIdChunk {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.0[..].serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PaletteIdChunk {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let voxels = Vec::<PaletteVoxel>::deserialize(deserializer)?;
+        let voxels = voxels.try_into()
+            .map_err(|voxels: Vec<_>| serde::de::Error::invalid_length(voxels.len(), &"4096 voxels"))?;
+        Ok(PaletteIdChunk(voxels))
+    }
+}
+
+/// Helper for World. Only chunks which hold anything are stored.
+#[derive(Serialize, Deserialize)]
+struct SparseWorld<C> {
+    chunks: Vec<ChunkEntry<C>>,
+}
+
+impl Serialize for World {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        SparseWorld {
+            chunks: self.iter_chunks()
+                .filter(|(_, chunk)| chunk.0.iter().any(|v| *v != PaletteVoxel::EMPTY))
+                .map(|(offset, chunk)| ChunkEntry {
+                    offset: offset.get_world_offset().into(),
+                    chunk,
+                })
+                .collect(),
+        }.serialize(serializer)
+    }
+}
+
+/// All loaded chunks are dirty, to get meshed.
+impl<'de> Deserialize<'de> for World {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ret = SparseWorld::<PaletteIdChunk>::deserialize(deserializer)?;
+        let mut world = World::default();
+        let mut dirty = DirtyChunks::new();
+        for entry in ret.chunks {
+            let index = to_chunk_index::<D::Error>(entry.offset)?;
+            world.insert_chunk(index, entry.chunk);
+            dirty.mark_cuboid(index.get_world_offset(), get_chunk_dimensions());
+        }
+        world.mark_dirty(dirty);
+        Ok(world)
+    }
+}
+
+/// Helper for Overlay. Empty chunks are stored too, because they erase.
+#[derive(Serialize, Deserialize)]
+struct Patch<C> {
+    chunks: Vec<ChunkEntry<C>>,
+    #[serde(default)]
+    removed: Vec<[i64; 3]>,
+    dirty: Vec<[i64; 3]>,
+}
+
+impl<C: Serialize> Serialize for Overlay<C> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        Patch {
+            chunks: self.chunks.iter()
+                .map(|(offset, chunk)| ChunkEntry {
+                    offset: offset.get_world_offset().into(),
+                    chunk,
+                })
+                .collect(),
+            removed: self.removed.iter()
+                .map(|offset| offset.get_world_offset().into())
+                .collect(),
+            dirty: self.dirty.iter()
+                .map(|offset| offset.get_world_offset().into())
+                .collect(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, C: Deserialize<'de>> Deserialize<'de> for Overlay<C> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ret = Patch::<C>::deserialize(deserializer)?;
+        let mut chunks = HashMap::with_capacity(ret.chunks.len());
+        for entry in ret.chunks {
+            chunks.insert(to_chunk_index::<D::Error>(entry.offset)?, entry.chunk);
+        }
+        let removed = ret.removed.into_iter()
+            .map(to_chunk_index::<D::Error>)
+            .collect::<Result<_, _>>()?;
+        let dirty = ret.dirty.into_iter()
+            .map(to_chunk_index::<D::Error>)
+            .collect::<Result<_, _>>()?;
+        Ok(Overlay {
+            chunks,
+            removed,
+            dirty: DirtyChunks(dirty),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::traits::Space;
+    use crate::world::Cow;
+
+    #[test]
+    fn world_roundtrip() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([-3, 20, 5].into(), PaletteVoxel(3));
+        // Changed back, so the chunk is empty.
+        cow.set([100, 0, 0].into(), PaletteVoxel(1));
+        cow.set([100, 0, 0].into(), PaletteVoxel::EMPTY);
+        cow.into_changes().apply(&mut world);
+
+        let data = bincode::serialize(&world).unwrap();
+        let loaded: World = bincode::deserialize(&data).unwrap();
+        assert_eq!(loaded.get([-3, 20, 5].into()), PaletteVoxel(3));
+        assert_eq!(loaded.iter_chunk_indices().count(), 1);
+        assert!(data.len() < 2 * 4096);
+    }
+
+    #[test]
+    fn patch_applies_later() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(2));
+        cow.into_changes().apply(&mut world);
+
+        // Erasing leaves an empty chunk in the overlay.
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel::EMPTY);
+        let data = bincode::serialize(&cow.into_changes()).unwrap();
+        let patch: Overlay = bincode::deserialize(&data).unwrap();
+        assert!(patch.get_dirty().contains(ChunkIndex::new_encompassing([1, 1, 1].into())));
+        world.take_dirty();
+        patch.apply(&mut world);
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel::EMPTY);
+        assert!(!world.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn misaligned_chunk() {
+        let patch = Patch::<PaletteIdChunk> {
+            chunks: vec![ChunkEntry {
+                offset: [1, 0, 0],
+                chunk: PaletteIdChunk::default(),
+            }],
+            removed: vec![],
+            dirty: vec![],
+        };
+        let data = bincode::serialize(&patch).unwrap();
+        assert!(bincode::deserialize::<Overlay>(&data).is_err());
+    }
+}
@@ -6,29 +6,35 @@
  *
  * TODO: which parts should end up in prefab?
  *
- * `feldspar::render` accepts an`ArrayMaterial` resource via `feldspar::renderer::create_voxel_mesh_bundle`,
- * and creates a `SmoothVoxelPbrBundle`.
- * 
- * To create the `ArrayMaterial`, provide a `LoadingTexture` resource and add the plugin.
- * When `State` reaches `Ready`, `ArrayMaterial` will be available as a resource.
+ * Meshes get drawn with `PaletteMaterial`, via `create_voxel_mesh_bundle`.
+ * How each voxel looks comes from the `Looks` resource.
+ *
+ * To create the material, provide a `LoadingTexture` resource and add the plugin.
+ * When `State` reaches `Ready`, `MeshMaterial` will be available as a resource.
  *
  * */
+mod material;
+
 use bevy::app;
+use bevy::ecs::component::Component;
 use bevy::prelude::*;
+use bevy::render::pipeline::PipelineDescriptor;
+use bevy::render::render_graph::RenderGraph;
 // Older version needed for block_mesh
 use block_mesh::{greedy_quads, GreedyQuadsBuffer, MergeVoxel, RIGHT_HANDED_Y_UP_CONFIG, UnorientedQuad};
 use feldspar::bb::mesh::PosNormMesh;
-use feldspar::prelude::{
-    spawn_array_material, ArrayMaterial, SdfVoxelPalette, VoxelRenderAssets, VoxelType, VoxelTypeInfo, VoxelMaterial,
-};
-use feldspar::renderer::create_voxel_mesh_bundle;
 use ndshape;
 
-use crate::indices::{to_i32_arr, usize_to_u32_arr, ChunkIndex};
+use crate::geometry::{Affine, Resampled};
+use crate::indices::{to_i32_arr, usize_to_u32_arr, ChunkIndex, Index, VoxelUnits};
 use crate::prefab::{ PaletteIdChunk, PaletteVoxel, World };
 use crate::re;
-use crate::traits::{IterableSpace, Space};
-use crate::world::{ Cow, FlatPaddedGridCuboid, View };
+use crate::traits::{Cuboid, Space};
+use crate::world::{ DirtyChunks, FlatPaddedCuboid, FlatPaddedGridCuboid, View };
+
+pub use material::{
+    create_voxel_mesh_bundle, Look, Looks, MeshMaterial, PaletteMaterial, VoxelMeshBundle, NO_LAYER,
+};
 
 type ChunkMeshShape = block_mesh::ndshape::ConstShape3u32::<18, 18, 18>;
 
@@ -38,28 +44,8 @@ pub struct Plugin;
 impl app::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
         app
-            .insert_resource(SdfVoxelPalette::new(vec![
-                VoxelTypeInfo {
-                    is_empty: true,
-                    material: VoxelMaterial::NULL,
-                },
-                VoxelTypeInfo {
-                    is_empty: false,
-                    material: VoxelMaterial(0),
-                },
-                VoxelTypeInfo {
-                    is_empty: false,
-                    material: VoxelMaterial(1),
-                },
-                VoxelTypeInfo {
-                    is_empty: false,
-                    material: VoxelMaterial(2),
-                },
-                VoxelTypeInfo {
-                    is_empty: false,
-                    material: VoxelMaterial(3),
-                },
-            ]))
+            .add_asset::<PaletteMaterial>()
+            .insert_resource(Looks::default())
             .add_state(TextureState::Loading)
             .add_system_set(
                 SystemSet::on_update(TextureState::Loading)
@@ -70,54 +56,60 @@ impl app::Plugin for Plugin {
 }
 
 
-/// Stores the material to be used for rendering.
-/// Get one from `spawn_array_material`.
-#[derive(Default)]
-pub struct MeshMaterial(pub Handle<ArrayMaterial>);
+/// To track which parts should be despawned and when
+pub struct ChunkMesh(pub ChunkIndex);
 
-impl From<Handle<ArrayMaterial>> for MeshMaterial {
-    fn from(v: Handle<ArrayMaterial>) -> Self {
-        Self(v)
+/// Despawns meshes of changed chunks.
+pub fn despawn_dirty<T: Component>(
+    commands: &mut Commands,
+    dirty: &DirtyChunks,
+    meshes: &Query<(Entity, &T)>,
+    get_chunk: impl Fn(&T) -> ChunkIndex,
+) {
+    for (entity, mesh) in meshes.iter() {
+        if dirty.contains(get_chunk(mesh)) {
+            commands.entity(entity).despawn()
+        }
     }
 }
 
-
-/// To track which parts should be despawned and when
-pub struct ChunkMesh;
-
+/// Meshes only chunks which changed.
+/// Looks changing cause meshing everything again.
 pub fn generate_meshes(
     mut commands: Commands,
-    world: Res<World>,
-    palette: Res<SdfVoxelPalette>,
+    mut world: ResMut<World>,
+    looks: Res<Looks>,
     //cutoff_height: Res<MeshCutoff>,
     mesh_material: Res<MeshMaterial>,
     mut meshes: ResMut<Assets<Mesh>>,
-    chunk_meshes: Query<Entity, With<ChunkMesh>>,
+    chunk_meshes: Query<(Entity, &ChunkMesh)>,
 ) {
-    // Get rid of all meshes
-    for cm in chunk_meshes.iter() {
-        commands.entity(cm).despawn()
+    let mut dirty = world.take_dirty();
+    if looks.is_changed() {
+        for index in world.iter_chunk_indices() {
+            dirty.mark_cuboid(index.get_world_offset(), [16, 16, 16]);
+        }
+    }
+    if dirty.is_empty() {
+        return;
     }
-    // And create the occupied ones again.
-    // Wasteful, I know. I'm testing!
-    for index in world.iter_chunk_indices() {
-        let mesh = generate_mesh_for_chunk(&world, &palette, index);
-        if let Some((mesh, materials)) = mesh {
+    despawn_dirty(&mut commands, &dirty, &chunk_meshes, |mesh| mesh.0);
+    for index in dirty.iter() {
+        let mesh = generate_mesh_for_chunk(&world, &looks, index);
+        if let Some((mesh, looks)) = mesh {
             commands
                 .spawn_bundle(
                     create_voxel_mesh_bundle(
                         mesh,
-                        materials,
-                        mesh_material.0.clone(),
+                        looks,
+                        &mesh_material,
                         &mut meshes,
                     )
                 )
                 .insert(Transform::from_translation(
-                    index
-                        .get_world_offset()
-                        .into()
+                    get_chunk_mesh_offset(index).into()
                 ))
-                .insert(ChunkMesh)
+                .insert(ChunkMesh(index))
                 ;
         }
     }
@@ -126,9 +118,20 @@ pub fn generate_meshes(
 /// A space which is affected by the Transform component before meshing.
 pub struct TransformMesh;
 
+/// Turns around the middle of the chunk.
+/// Padded so that the outer faces get meshed too.
+fn transform_chunk(space: &PaletteIdChunk, transform: &Affine) -> FlatPaddedCuboid<PaletteVoxel> {
+    let chunk = space.crop([0, 0, 0].into(), [16, 16, 16]);
+    let middle = chunk.get_dimensions().map(|d| d as i32 / 2);
+    let centered = chunk.translate(middle.map(|v| -v));
+    let transformed = Resampled::new(&centered, transform);
+    let moved = transformed.translate(middle);
+    moved.pad([1, 1, 1], [1, 1, 1]).into()
+}
+
 pub fn generate_transformeshes(
     mut commands: Commands,
-    palette: Res<SdfVoxelPalette>,
+    looks: Res<Looks>,
     //cutoff_height: Res<MeshCutoff>,
     mesh_material: Res<MeshMaterial>,
     ts_spaces: Query<(&PaletteIdChunk, &Transform)>,
@@ -141,68 +144,46 @@ pub fn generate_transformeshes(
     }
 
     for (space, transform) in ts_spaces.iter() {
-        let mut world = World::default();
-        let mut overlay = Cow::new(&world);
-        let centered = View::<_, re::ConstAnyShape<18, 18, 18>>::new(
-            space,
-            [9, 9, 9].into(),
-        );
-        // This is kind of lousy, may have holes inside.
-        // It would have been better to perform the inverse transformation,
-        // but the API would have to expose source's occupancy.
-        centered.visit_indices(|index| {
-            let voxel = centered.get(index);
-            let tf_index = transform.mul_vec3(index.into());
-            if voxel != PaletteVoxel::EMPTY {
-                overlay.set(tf_index.into(), voxel);
-            }
-        });
-        let changes = overlay.into_changes();
-        changes.apply(&mut world);
-        // Screw accuracy. Alien chunks can only be close to the middle.
-        let view = View::<_, re::ConstAnyShape<18, 18, 18>>::new(
-            &world,
-            [-9, -9, -9].into(),
-        );
-        let quads = generate_greedy_buffer(view.clone());
-
-        let material_lookup = |quad: &UnorientedQuad| {
-            let material = to_material(&palette, view.get(to_i32_arr(quad.minimum).into()));
-            [material, material, material, material]
+        let padded = transform_chunk(space, &(*transform).into());
+        let quads = generate_greedy_buffer_runtime(&padded);
+
+        let look_lookup = |quad: &UnorientedQuad| {
+            let offset = padded.get_offset() + VoxelUnits(to_i32_arr(quad.minimum));
+            let look = looks.get(padded.get(offset));
+            [look, look, look, look]
         };
 
-        let mesh = mesh_from_quads(quads, &view, material_lookup);
-        if let Some((mesh, materials)) = mesh {
+        let mesh = mesh_from_quads(quads, &padded, look_lookup);
+        if let Some((mesh, looks)) = mesh {
             commands
                 .spawn_bundle(
                     create_voxel_mesh_bundle(
                         mesh,
-                        materials,
-                        mesh_material.0.clone(),
+                        looks,
+                        &mesh_material,
                         &mut meshes,
                     )
                 )
+                .insert(Transform::from_translation(padded.get_offset().into()))
                 .insert(TransformMesh)
                 ;
         }
     }
 }
 
-fn generate_greedy_buffer<V, S, Shape>(
-    view: View<S, Shape>,
+pub fn generate_greedy_buffer_fast<V, Shape>(
+    view: &FlatPaddedGridCuboid<V, Shape>,
 ) -> GreedyQuadsBuffer
     where
-    V: MergeVoxel,
-    S: Space<Voxel=V>,
+    V: MergeVoxel + Copy + Default,
     Shape: re::ConstShape,
 {
-    let samples = view.into_vec();
+    let samples = view.get_samples();
     let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-
     let mut buffer = GreedyQuadsBuffer::new(samples.len());
 
     greedy_quads(
-        &samples,
+        samples,
         &ndshape::RuntimeShape::<u32, 3>::new(usize_to_u32_arr(<Shape as re::ConstShape>::ARRAY)),
         [0, 0, 0],
         [
@@ -216,26 +197,26 @@ fn generate_greedy_buffer<V, S, Shape>(
     buffer
 }
 
-
-pub fn generate_greedy_buffer_fast<V, Shape>(
-    view: &FlatPaddedGridCuboid<V, Shape>,
+/// Like `generate_greedy_buffer_fast`, but for dimensions chosen at runtime.
+pub fn generate_greedy_buffer_runtime<V>(
+    view: &FlatPaddedCuboid<V>,
 ) -> GreedyQuadsBuffer
     where
     V: MergeVoxel + Copy + Default,
-    Shape: re::ConstShape,
 {
     let samples = view.get_samples();
+    let dimensions = usize_to_u32_arr(view.get_dimensions());
     let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
     let mut buffer = GreedyQuadsBuffer::new(samples.len());
 
     greedy_quads(
         samples,
-        &ndshape::RuntimeShape::<u32, 3>::new(usize_to_u32_arr(<Shape as re::ConstShape>::ARRAY)),
+        &ndshape::RuntimeShape::<u32, 3>::new(dimensions),
         [0, 0, 0],
         [
-            <Shape as re::ConstShape>::ARRAY[0] as u32 - 1,
-            <Shape as re::ConstShape>::ARRAY[1] as u32 - 1,
-            <Shape as re::ConstShape>::ARRAY[2] as u32 - 1,
+            dimensions[0] - 1,
+            dimensions[1] - 1,
+            dimensions[2] - 1,
         ],
         &faces,
         &mut buffer,
@@ -243,12 +224,20 @@ pub fn generate_greedy_buffer_fast<V, Shape>(
     buffer
 }
 
+/// Where the mesh of the chunk begins.
+/// The mesh includes a layer of neighbouring voxels on each side,
+/// so that faces between chunks are correct.
+pub fn get_chunk_mesh_offset(index: ChunkIndex) -> Index {
+    let offset = index.get_world_offset();
+    [offset.x() - 1, offset.y() - 1, offset.z() - 1].into()
+}
+
 fn generate_mesh_for_chunk(
     world: &World,
-    palette: &SdfVoxelPalette,
+    looks: &Looks,
     index: ChunkIndex,
-) -> Option<(PosNormMesh, Vec<[u8; 4]>)> {
-    let view_offset = index.get_world_offset();
+) -> Option<(PosNormMesh, Vec<Look>)> {
+    let view_offset = get_chunk_mesh_offset(index);
     let view = View::<_, re::ConstAnyShape<18, 18, 18>>::new(
         &world,
         view_offset,
@@ -268,12 +257,12 @@ fn generate_mesh_for_chunk(
         &mut buffer,
     );
     
-    let material_lookup = |quad: &UnorientedQuad| {
-        let material = to_material(&palette, view.get(to_i32_arr(quad.minimum).into()));
-        [material, material, material, material]
+    let look_lookup = |quad: &UnorientedQuad| {
+        let look = looks.get(view.get(to_i32_arr(quad.minimum).into()));
+        [look, look, look, look]
     };
     
-    mesh_from_quads(buffer, &view, material_lookup)
+    mesh_from_quads(buffer, &view, look_lookup)
 }
 
 /// Caution, the `vertex_map` function receives raw quads.
@@ -321,13 +310,6 @@ where
     }
 }
 
-fn to_material(palette: &SdfVoxelPalette, v: PaletteVoxel) -> [u8; 4] {
-    let mut materials = [0; 4];
-    let info = palette.get_voxel_type_info(VoxelType(v.0));
-    materials[info.material.0 as usize] = 1;
-    materials
-}
-
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub enum TextureState {
     Loading,
@@ -337,20 +319,47 @@ pub enum TextureState {
 /// A unique type for a resource containing the texture handle for this module.
 pub struct LoadingTexture(pub Handle<Texture>);
 
-// From feldspar
 fn wait_for_assets_loaded(
     mut commands: Commands,
     loading_texture: Res<LoadingTexture>,
     mut textures: ResMut<Assets<Texture>>,
-    mut array_materials: ResMut<Assets<ArrayMaterial>>,
+    mut materials: ResMut<Assets<PaletteMaterial>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut render_graph: ResMut<RenderGraph>,
     mut state: ResMut<State<TextureState>>,
 ) {
     if textures.get(&loading_texture.0).is_some() {
-        let params = VoxelRenderAssets {
-            mesh_base_color: loading_texture.0.clone(),
-            image_count: 4,
-        };
-        spawn_array_material::<MeshMaterial>(&params, commands, array_materials, textures);
+        commands.insert_resource(material::create_mesh_material(
+            loading_texture.0.clone(),
+            &mut textures,
+            &mut materials,
+            &mut shaders,
+            &mut pipelines,
+            &mut render_graph,
+        ));
         state.set(TextureState::Ready).unwrap();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::traits::MutChunk;
+
+    #[test]
+    fn half_turn_in_place() {
+        let mut chunk = PaletteIdChunk::default();
+        chunk.set([1, 2, 3].into(), PaletteVoxel(1));
+        // Half a turn around y.
+        let transform = Affine {
+            matrix: [[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]],
+            translation: [0.0, 0.0, 0.0],
+        };
+        let turned = transform_chunk(&chunk, &transform);
+        assert_eq!(turned.get_offset(), [-1, -1, -1].into());
+        assert_eq!(turned.get_dimensions(), [18, 18, 18]);
+        assert_eq!(turned.get([14, 2, 12].into()), PaletteVoxel(1));
+        assert_eq!(turned.get([1, 2, 3].into()), PaletteVoxel::EMPTY);
+    }
+}
@@ -0,0 +1,237 @@
+/*! A material for voxel meshes, with any number of voxel kinds.
+ *
+ * Each vertex carries its layer of the texture array, and a colour.
+ * Layers which the texture array doesn't have show the colour instead.
+ * Adding voxel kinds needs no changes here, or in the shaders.
+ */
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::pipeline::{PipelineDescriptor, PrimitiveTopology, RenderPipeline};
+use bevy::render::render_graph::{base, AssetRenderResourcesNode, RenderGraph};
+use bevy::render::renderer::RenderResources;
+use bevy::render::shader::{ShaderStage, ShaderStages};
+use feldspar::bb::mesh::PosNormMesh;
+
+use crate::prefab::PaletteVoxel;
+
+
+/// The layer of looks which only have a colour.
+pub const NO_LAYER: u32 = u32::MAX;
+
+pub const ATTRIBUTE_LAYER: &str = "Vertex_Layer";
+pub const ATTRIBUTE_COLOUR: &str = "Vertex_Colour";
+
+/// What a vertex looks like.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Look {
+    /// Layer of the texture array, or `NO_LAYER`.
+    pub layer: u32,
+    /// Shown when the texture array doesn't have the layer.
+    pub colour: [u8; 4],
+}
+
+impl Look {
+    /// Stands out, so that voxels missing from `Looks` get noticed.
+    pub const MISSING: Look = Look {
+        layer: NO_LAYER,
+        colour: [255, 0, 255, 255],
+    };
+
+    pub fn from_colour(colour: [u8; 4]) -> Self {
+        Self { layer: NO_LAYER, colour }
+    }
+}
+
+/// The resource telling how each voxel kind looks.
+/// The position in the list is the id of the voxel.
+#[derive(Clone, Debug)]
+pub struct Looks(pub Vec<Look>);
+
+impl Looks {
+    pub fn get(&self, voxel: PaletteVoxel) -> Look {
+        self.0.get(voxel.0 as usize)
+            .copied()
+            .unwrap_or(Look::MISSING)
+    }
+}
+
+/// Matches the bundled texture: empty, and then one voxel per layer.
+impl Default for Looks {
+    fn default() -> Self {
+        Self(vec![
+            Look::from_colour([0, 0, 0, 0]),
+            Look { layer: 0, colour: [77, 153, 51, 255] },
+            Look { layer: 1, colour: [128, 128, 128, 255] },
+            Look { layer: 2, colour: [153, 102, 51, 255] },
+            Look { layer: 3, colour: [179, 204, 230, 255] },
+        ])
+    }
+}
+
+#[derive(RenderResources, TypeUuid)]
+#[uuid = "5f0b0a4e-8f2c-4b7e-9a53-2c1d7e4b6a90"]
+pub struct PaletteMaterial {
+    /// Texture array. Its layers are square, and stacked vertically in the source image.
+    pub base_color: Handle<Texture>,
+}
+
+/// The material and the pipeline to render voxel meshes with.
+pub struct MeshMaterial {
+    pub material: Handle<PaletteMaterial>,
+    pub pipeline: Handle<PipelineDescriptor>,
+}
+
+#[derive(Bundle)]
+pub struct VoxelMeshBundle {
+    #[bundle]
+    pub mesh: MeshBundle,
+    pub material: Handle<PaletteMaterial>,
+}
+
+/// `looks` has an entry for each vertex of `mesh`.
+pub fn create_voxel_mesh_bundle(
+    mesh: PosNormMesh,
+    looks: Vec<Look>,
+    material: &MeshMaterial,
+    meshes: &mut Assets<Mesh>,
+) -> VoxelMeshBundle {
+    let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    render_mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, mesh.positions);
+    render_mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, mesh.normals);
+    render_mesh.set_attribute(
+        ATTRIBUTE_LAYER,
+        VertexAttributeValues::Uint(looks.iter().map(|look| look.layer).collect()),
+    );
+    render_mesh.set_attribute(
+        ATTRIBUTE_COLOUR,
+        VertexAttributeValues::Uchar4Norm(looks.iter().map(|look| look.colour).collect()),
+    );
+    render_mesh.set_indices(Some(Indices::U32(mesh.indices)));
+
+    VoxelMeshBundle {
+        mesh: MeshBundle {
+            mesh: meshes.add(render_mesh),
+            render_pipelines: RenderPipelines::from_pipelines(vec![
+                RenderPipeline::new(material.pipeline.clone()),
+            ]),
+            ..Default::default()
+        },
+        material: material.material.clone(),
+    }
+}
+
+/// Turns the texture into an array, and sets up everything to render with it.
+/// Call once.
+pub fn create_mesh_material(
+    base_color: Handle<Texture>,
+    textures: &mut Assets<Texture>,
+    materials: &mut Assets<PaletteMaterial>,
+    shaders: &mut Assets<Shader>,
+    pipelines: &mut Assets<PipelineDescriptor>,
+    render_graph: &mut RenderGraph,
+) -> MeshMaterial {
+    if let Some(texture) = textures.get_mut(&base_color) {
+        let layers = texture.size.height / texture.size.width;
+        texture.reinterpret_stacked_2d_as_array(layers.max(1));
+    }
+
+    let pipeline = pipelines.add(PipelineDescriptor::default_config(ShaderStages {
+        vertex: shaders.add(Shader::from_glsl(ShaderStage::Vertex, VERTEX_SHADER)),
+        fragment: Some(shaders.add(Shader::from_glsl(ShaderStage::Fragment, FRAGMENT_SHADER))),
+    }));
+    render_graph.add_system_node(
+        "palette_material",
+        AssetRenderResourcesNode::<PaletteMaterial>::new(true),
+    );
+    render_graph
+        .add_node_edge("palette_material", base::node::MAIN_PASS)
+        .unwrap();
+
+    MeshMaterial {
+        material: materials.add(PaletteMaterial { base_color }),
+        pipeline,
+    }
+}
+
+const VERTEX_SHADER: &str = r#"
+#version 450
+
+layout(location = 0) in vec3 Vertex_Position;
+layout(location = 1) in vec3 Vertex_Normal;
+layout(location = 2) in uint Vertex_Layer;
+layout(location = 3) in vec4 Vertex_Colour;
+
+layout(location = 0) out vec3 v_Normal;
+layout(location = 1) out vec2 v_Uv;
+layout(location = 2) flat out uint v_Layer;
+layout(location = 3) out vec4 v_Colour;
+
+layout(set = 0, binding = 0) uniform CameraViewProj {
+    mat4 ViewProj;
+};
+layout(set = 1, binding = 0) uniform Transform {
+    mat4 Model;
+};
+
+void main() {
+    // The texture covers each voxel face once,
+    // and repeats over faces merged together.
+    vec3 facing = abs(Vertex_Normal);
+    if (facing.x > facing.y && facing.x > facing.z) {
+        v_Uv = Vertex_Position.zy;
+    } else if (facing.y > facing.z) {
+        v_Uv = Vertex_Position.xz;
+    } else {
+        v_Uv = Vertex_Position.xy;
+    }
+    v_Normal = mat3(Model) * Vertex_Normal;
+    v_Layer = Vertex_Layer;
+    v_Colour = Vertex_Colour;
+    gl_Position = ViewProj * Model * vec4(Vertex_Position, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+#version 450
+
+layout(location = 0) in vec3 v_Normal;
+layout(location = 1) in vec2 v_Uv;
+layout(location = 2) flat in uint v_Layer;
+layout(location = 3) in vec4 v_Colour;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 2, binding = 0) uniform texture2DArray PaletteMaterial_base_color;
+layout(set = 2, binding = 1) uniform sampler PaletteMaterial_base_color_sampler;
+
+void main() {
+    vec4 colour = v_Colour;
+    uint layers = uint(textureSize(
+        sampler2DArray(PaletteMaterial_base_color, PaletteMaterial_base_color_sampler),
+        0
+    ).z);
+    if (v_Layer < layers) {
+        colour = texture(
+            sampler2DArray(PaletteMaterial_base_color, PaletteMaterial_base_color_sampler),
+            vec3(fract(v_Uv), float(v_Layer))
+        );
+    }
+    // Some light from above, and some from everywhere.
+    vec3 light = normalize(vec3(0.3, 1.0, 0.5));
+    float brightness = 0.4 + 0.6 * max(dot(normalize(v_Normal), light), 0.0);
+    o_Target = vec4(colour.rgb * brightness, colour.a);
+}
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing() {
+        let looks = Looks::default();
+        assert_eq!(looks.get(PaletteVoxel(1)).layer, 0);
+        assert_eq!(looks.get(PaletteVoxel(200)), Look::MISSING);
+    }
+}
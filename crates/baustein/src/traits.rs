@@ -1,6 +1,7 @@
 /*! Traits for easy access to voxels */
 
 use feldspar_map::units::VoxelUnits;
+use crate::geometry::{Axis, Crop, Pad, Reoriented, Symmetry, Translate};
 use crate::indices::{ Index, usize_to_i32_arr };
 
 
@@ -28,12 +29,76 @@ pub trait Space {
         }
     }
 
+    /// Covers only the voxels which both spaces cover.
     fn zip<'a, 'b, T: Copy, S>(&'a self, other: &'b S) -> Zip<&'a Self, &'b S>
         where S: Space<Voxel=T>
     {
         Zip {
             left: self,
             right: other,
+            coverage: Coverage::Intersection,
+        }
+    }
+
+    /// Covers the voxels of both spaces, and whatever lies between them.
+    fn zip_union<'a, 'b, T: Copy, S>(&'a self, other: &'b S) -> Zip<&'a Self, &'b S>
+        where S: Space<Voxel=T>
+    {
+        Zip {
+            left: self,
+            right: other,
+            coverage: Coverage::Union,
+        }
+    }
+
+    /// Moves the space by `shift`.
+    fn translate(&self, shift: [i32; 3]) -> Translate<&Self> {
+        Translate {
+            space: self,
+            shift,
+        }
+    }
+
+    /// Covers only the given extent, giving default voxels outside.
+    fn crop(&self, offset: Index, dimensions: [usize; 3]) -> Crop<&Self> {
+        Crop {
+            space: self,
+            offset,
+            dimensions,
+        }
+    }
+
+    /// Adds default voxels on the low and on the high side of each axis.
+    fn pad(&self, low: [usize; 3], high: [usize; 3]) -> Pad<&Self>
+        where Self: Cuboid
+    {
+        Pad::new(self, low, high)
+    }
+
+    /// Rotates or mirrors the space, keeping its lowest corner in place.
+    fn reorient(&self, symmetry: Symmetry) -> Reoriented<&Self>
+        where Self: Cuboid
+    {
+        Reoriented::new(self, symmetry)
+    }
+
+    fn mirror(&self, axis: Axis) -> Reoriented<&Self>
+        where Self: Cuboid
+    {
+        self.reorient(Symmetry::mirror(axis))
+    }
+
+    /// Fails unless both spaces cover exactly the same voxels.
+    fn try_zip<'a, 'b, T: Copy, S>(&'a self, other: &'b S)
+        -> Result<Zip<&'a Self, &'b S>, ExtentMismatch>
+        where
+        Self: Cuboid,
+        S: Space<Voxel=T> + Cuboid,
+    {
+        if Bounds::of(self) == Bounds::of(other) {
+            Ok(self.zip(other))
+        } else {
+            Err(ExtentMismatch)
         }
     }
 }
@@ -77,8 +142,9 @@ impl<T: Copy, E, F> Space for Map<E, F>
 impl<E, F> IterableSpace for Map<E, F>
     where E: IterableSpace,
 {
-    fn visit_indices<G: FnMut(Index)>(&self, f: G) {
-        self.space.visit_indices(f)
+    type Indices = E::Indices;
+    fn indices(&self) -> Self::Indices {
+        self.space.indices()
     }
 }
 
@@ -112,8 +178,9 @@ impl<T: Copy, E: Space, F> Space for MapIndex<E, F>
 impl<E, F> IterableSpace for MapIndex<E, F>
     where E: IterableSpace,
 {
-    fn visit_indices<G: FnMut(Index)>(&self, f: G) {
-        self.space.visit_indices(f)
+    type Indices = E::Indices;
+    fn indices(&self) -> Self::Indices {
+        self.space.indices()
     }
 }
 
@@ -128,9 +195,86 @@ impl<S, F> Cuboid for MapIndex<S, F>
     }
 }
 
+/// Which voxels a combination of spaces covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Coverage {
+    /// Only voxels covered by every space.
+    Intersection,
+    /// The smallest cuboid containing all the spaces.
+    /// Spaces must give some voxel also outside of their own extent.
+    Union,
+}
+
+/// The spaces don't cover the same voxels.
+#[derive(Debug)]
+pub struct ExtentMismatch;
+
+/// Corners of a cuboid. `end` is not inside.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Bounds {
+    start: Index,
+    end: Index,
+}
+
+impl Bounds {
+    fn of<C: Cuboid + ?Sized>(cuboid: &C) -> Self {
+        Self {
+            start: cuboid.get_offset(),
+            end: cuboid.get_beyond_opposite_corner(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.end.x() <= self.start.x()
+            || self.end.y() <= self.start.y()
+            || self.end.z() <= self.start.z()
+    }
+
+    fn combine(self, other: Bounds, coverage: Coverage) -> Self {
+        match coverage {
+            Coverage::Intersection => Self {
+                start: self.start.0.max(other.start.0).into(),
+                end: self.end.0.min(other.end.0).into(),
+            },
+            // Empty spaces don't cover anything, wherever they are.
+            Coverage::Union => if self.is_empty() {
+                other
+            } else if other.is_empty() {
+                self
+            } else {
+                Self {
+                    start: self.start.0.min(other.start.0).into(),
+                    end: self.end.0.max(other.end.0).into(),
+                }
+            },
+        }
+    }
+
+    fn get_dimensions(&self) -> [usize; 3] {
+        let size = |start: i32, end: i32| (end - start).max(0) as usize;
+        [
+            size(self.start.x(), self.end.x()),
+            size(self.start.y(), self.end.y()),
+            size(self.start.z(), self.end.z()),
+        ]
+    }
+}
+
 pub struct Zip<E, F> {
     left: E,
     right: F,
+    coverage: Coverage,
+}
+
+impl<E: Cuboid, F: Cuboid> Zip<E, F> {
+    fn get_bounds(&self) -> Bounds {
+        Bounds::of(&self.left).combine(Bounds::of(&self.right), self.coverage)
+    }
+
+    /// True when both spaces cover the same voxels.
+    pub fn is_matched(&self) -> bool {
+        Bounds::of(&self.left) == Bounds::of(&self.right)
+    }
 }
 
 impl<T: Copy, U: Copy, E, F> Space for Zip<E, F>
@@ -145,47 +289,227 @@ impl<T: Copy, U: Copy, E, F> Space for Zip<E, F>
     }
 }
 
-// FIXME: this totally ignores the second space. Should be okay for now.
+/// Goes over every voxel of the cuboid, even if the spaces are sparse.
 impl<E, F> IterableSpace for Zip<E, F>
-    where E: IterableSpace,
+    where E: Cuboid, F: Cuboid,
 {
-    fn visit_indices<G: FnMut(Index)>(&self, f: G) {
-        self.left.visit_indices(f)
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new(self.get_offset(), self.get_dimensions())
     }
 }
 
-// FIXME: this also ignores second space.
 impl<S, T> Cuboid for Zip<S, T>
-    where S: Cuboid
+    where S: Cuboid, T: Cuboid,
 {
     fn get_offset(&self) -> Index {
-        self.left.get_offset()
+        self.get_bounds().start
     }
     fn get_dimensions(&self) -> [usize; 3] {
-        self.left.get_dimensions()
+        self.get_bounds().get_dimensions()
     }
 }
 
+/// Like `Zip`, but for more spaces, stored in a tuple.
+/// Voxels are tuples of voxels of each space.
+/// Only tuples of 3 and 4 spaces are supported. For 2, use `Zip`.
+pub struct ZipAll<T> {
+    spaces: T,
+    coverage: Coverage,
+}
+
+impl<T> ZipAll<T> {
+    pub fn new(spaces: T, coverage: Coverage) -> Self {
+        Self { spaces, coverage }
+    }
+}
+
+macro_rules! impl_zip_all {
+    ($first:ident $first_voxel:ident $first_name:ident, $($space:ident $voxel:ident $name:ident),+) => {
+        impl<$first, $first_voxel, $($space, $voxel),+> Space for ZipAll<($first, $($space),+)>
+            where
+            $first_voxel: Copy,
+            $first: Space<Voxel=$first_voxel>,
+            $($voxel: Copy, $space: Space<Voxel=$voxel>),+
+        {
+            type Voxel = ($first_voxel, $($voxel),+);
+            fn get(&self, offset: Index) -> Self::Voxel {
+                let ($first_name, $($name),+) = &self.spaces;
+                ($first_name.get(offset), $($name.get(offset)),+)
+            }
+        }
+
+        impl<$first: Cuboid, $($space: Cuboid),+> ZipAll<($first, $($space),+)> {
+            /// Fails unless all spaces cover exactly the same voxels.
+            pub fn try_new(spaces: ($first, $($space),+)) -> Result<Self, ExtentMismatch> {
+                let zipped = Self::new(spaces, Coverage::Intersection);
+                if zipped.is_matched() {
+                    Ok(zipped)
+                } else {
+                    Err(ExtentMismatch)
+                }
+            }
+
+            /// True when all spaces cover the same voxels.
+            pub fn is_matched(&self) -> bool {
+                let ($first_name, $($name),+) = &self.spaces;
+                let first = Bounds::of($first_name);
+                true $(&& Bounds::of($name) == first)+
+            }
+
+            fn get_bounds(&self) -> Bounds {
+                let ($first_name, $($name),+) = &self.spaces;
+                Bounds::of($first_name)
+                    $(.combine(Bounds::of($name), self.coverage))+
+            }
+        }
+
+        impl<$first: Cuboid, $($space: Cuboid),+> Cuboid for ZipAll<($first, $($space),+)> {
+            fn get_offset(&self) -> Index {
+                self.get_bounds().start
+            }
+            fn get_dimensions(&self) -> [usize; 3] {
+                self.get_bounds().get_dimensions()
+            }
+        }
+
+        /// Goes over every voxel of the cuboid, even if the spaces are sparse.
+        impl<$first: Cuboid, $($space: Cuboid),+> IterableSpace for ZipAll<($first, $($space),+)> {
+            type Indices = CuboidIndices;
+            fn indices(&self) -> Self::Indices {
+                CuboidIndices::new(self.get_offset(), self.get_dimensions())
+            }
+        }
+    };
+}
+
+impl_zip_all!(A VA a, B VB b, C VC c);
+impl_zip_all!(A VA a, B VB b, C VC c, D VD d);
+
 
 pub trait MutChunk {
     type Voxel: Copy;
     fn set(&mut self, offset: Index, value: Self::Voxel);
 }
 
+/// Chunks which know how much memory they take.
+pub trait ChunkSize {
+    /// Approximate memory taken by the voxels, in bytes.
+    fn get_size(&self) -> usize;
+}
+
 // TODO: fold into Space.
 // The folding will need some extra logic to align with underlyning chunks
 pub trait IterableSpace {
-    // Can't be arsed to code an iterator.
-    // Waiting for generators, and maybe monads.
-    fn visit_indices<F: FnMut(Index)>(&self, f: F);
+    /// Doesn't borrow the space, so that it can be used alongside mutation.
+    type Indices: Iterator<Item=Index>;
+
+    /// Indices of all the voxels the space holds.
+    fn indices(&self) -> Self::Indices;
+
+    /// Indices together with the voxels at them.
+    fn voxels(&self) -> Voxels<'_, Self>
+        where Self: Space + Sized
+    {
+        Voxels {
+            space: self,
+            indices: self.indices(),
+        }
+    }
+
+    fn visit_indices<F: FnMut(Index)>(&self, f: F) {
+        self.indices().for_each(f)
+    }
 }
 
 impl<T> IterableSpace for &T
     where
     T: IterableSpace,
 {
-    fn visit_indices<F: FnMut(Index)>(&self, f: F) {
-        (*self).visit_indices(f)
+    type Indices = T::Indices;
+    fn indices(&self) -> Self::Indices {
+        (*self).indices()
+    }
+}
+
+pub struct Voxels<'a, S: IterableSpace> {
+    space: &'a S,
+    indices: S::Indices,
+}
+
+impl<'a, S> Iterator for Voxels<'a, S>
+    where S: Space + IterableSpace
+{
+    type Item = (Index, S::Voxel);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.indices.next().map(|index| (index, self.space.get(index)))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+/// All indices of a cuboid, in the order of linear indices of ndshape shapes:
+/// x changes fastest.
+#[derive(Clone, Debug)]
+pub struct CuboidIndices {
+    offset: Index,
+    dimensions: [usize; 3],
+    next: usize,
+    end: usize,
+}
+
+impl CuboidIndices {
+    pub fn new(offset: Index, dimensions: [usize; 3]) -> Self {
+        Self {
+            offset,
+            dimensions,
+            next: 0,
+            end: dimensions[0] * dimensions[1] * dimensions[2],
+        }
+    }
+}
+
+impl Iterator for CuboidIndices {
+    type Item = Index;
+    fn next(&mut self) -> Option<Index> {
+        if self.next >= self.end {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+        let [x, y, _] = self.dimensions;
+        let index = [i % x, (i / x) % y, i / (x * y)];
+        Some(self.offset + VoxelUnits(usize_to_i32_arr(index).into()))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.end - self.next;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for CuboidIndices {}
+
+/// Indices moved by the same distance.
+#[derive(Clone, Debug)]
+pub struct ShiftedIndices<I> {
+    indices: I,
+    shift: [i32; 3],
+}
+
+impl<I> ShiftedIndices<I> {
+    pub fn new(indices: I, shift: [i32; 3]) -> Self {
+        Self { indices, shift }
+    }
+}
+
+impl<I: Iterator<Item=Index>> Iterator for ShiftedIndices<I> {
+    type Item = Index;
+    fn next(&mut self) -> Option<Index> {
+        self.indices.next().map(|index| index + VoxelUnits(self.shift.into()))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
     }
 }
 
@@ -214,22 +538,13 @@ impl<T> Cuboid for &T
     }
 }
 
-/*
-impl IterableSpace for Cuboid {
-    fn visit_indices<F: FnMut(Index)>(&self, f: F) {
-        let start = self.get_offset();
-        let end = self.get_beyond_opposite_corner();
-        for 
-    }
-}
-*/
-
 // TODO: a Chunk trait should include the shape.
 // a World trait should include the grid
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::re::Shape;
     use crate::world::FlatPaddedCuboid;
     
     fn t<S: Space<Voxel=()> + Cuboid + IterableSpace>(s: &S) {
@@ -238,4 +553,62 @@ mod test {
             .into();
     }
 
+    #[test]
+    fn zip_extents() {
+        let left = FlatPaddedCuboid::<u8>::new([0, 0, 0].into(), [4, 4, 4]);
+        let right = FlatPaddedCuboid::<u8>::new([2, -1, 0].into(), [4, 4, 2]);
+        let both = left.zip(&right);
+        assert_eq!(both.get_offset(), [2, 0, 0].into());
+        assert_eq!(both.get_dimensions(), [2, 3, 2]);
+        assert_eq!(both.indices().count(), 2 * 3 * 2);
+        let either = left.zip_union(&right);
+        assert_eq!(either.get_offset(), [0, -1, 0].into());
+        assert_eq!(either.get_dimensions(), [6, 5, 4]);
+        assert!(!both.is_matched());
+        assert!(left.try_zip(&right).is_err());
+        assert!(left.try_zip(&left).is_ok());
+    }
+
+    #[test]
+    fn zip_disjoint() {
+        let left = FlatPaddedCuboid::<u8>::new([0, 0, 0].into(), [2, 2, 2]);
+        let right = FlatPaddedCuboid::<u8>::new([5, 0, 0].into(), [2, 2, 2]);
+        assert_eq!(left.zip(&right).indices().count(), 0);
+    }
+
+    #[test]
+    fn zip_all() {
+        let a = FlatPaddedCuboid::<u8>::new([0, 0, 0].into(), [2, 2, 2]);
+        let b = a.map(|_| 1u16);
+        let c = FlatPaddedCuboid::<u8>::new([1, 0, 0].into(), [2, 2, 2]);
+        let all = ZipAll::new((&a, &b, &c), Coverage::Intersection);
+        assert_eq!(all.get_offset(), [1, 0, 0].into());
+        assert_eq!(all.get_dimensions(), [1, 2, 2]);
+        assert_eq!(all.get([1, 0, 0].into()), (0, 1, 0));
+        assert!(ZipAll::try_new((&a, &b, &c)).is_err());
+        assert!(ZipAll::try_new((&a, &b, &a)).is_ok());
+    }
+
+    #[test]
+    fn cuboid_order() {
+        let shape = crate::re::RuntimeShape::new([3, 2, 4]);
+        let offset: Index = [-1, 5, 2].into();
+        let indices: Vec<Index> = CuboidIndices::new(offset, [3, 2, 4]).collect();
+        assert_eq!(indices.len(), 24);
+        for (i, index) in indices.into_iter().enumerate() {
+            let expected = offset + VoxelUnits(usize_to_i32_arr(shape.delinearize(i)).into());
+            assert_eq!(index, expected);
+        }
+    }
+
+    #[test]
+    fn voxels() {
+        let empty = FlatPaddedCuboid::<u8>::new([0, 0, 0].into(), [2, 2, 2]);
+        let space: FlatPaddedCuboid<u8> = empty.map_index(|i, _| i.x() as u8).into();
+        let ones = space.voxels().filter(|(_, v)| *v == 1).count();
+        assert_eq!(ones, 4);
+        let first = space.map(|v| v * 2).voxels().find(|(_, v)| *v == 2);
+        assert_eq!(first, Some(([1, 0, 0].into(), 2)));
+    }
+
 }
@@ -1,98 +1,736 @@
 /*! Voxel storage */
 use feldspar_map::units::VoxelUnits;
 use ndshape::{ ConstShape, RuntimeShape };
-use std::collections::HashMap;
-use crate::indices::{to_usize_arr, usize_to_i32_arr, ChunkIndex, Index};
-use crate::prefab::{ PaletteIdChunk, PaletteVoxel, World };
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::marker::PhantomData;
+use std::{iter, mem, vec};
+use crate::indices::{to_u32_arr, to_usize_arr, usize_to_i32_arr, ChunkIndex, ChunkShape, Index};
+use crate::geometry::{Crop, Pad, Reoriented, Resampled, Translate};
+use crate::prefab::{ PaletteIdChunk, PaletteVoxel };
 use crate::re;
-use crate::traits::{Cuboid as Extent, Space, IterableSpace, Map, MapIndex, Zip};
+use crate::traits::{Cuboid as Extent, ChunkSize, CuboidIndices, Space, IterableSpace, Map, MapIndex, ShiftedIndices, Zip, ZipAll};
 
 // Used traits
 use ndshape::Shape;
 use crate::traits::MutChunk;
 
-/// A naive copy-on-write overlay over a World.
-/// Its changes can be eventually applied to the underlying World.
-pub struct Cow<'a> {
-    base: &'a World,
-    overlaid: HashMap<ChunkIndex, PaletteIdChunk>,
+/// Chunks which changed since they were last taken.
+/// Changing a voxel on the face of a chunk marks the neighbouring chunk too,
+/// because the mesh of the neighbour depends on it.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct DirtyChunks(pub(crate) HashSet<ChunkIndex>);
+
+impl DirtyChunks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the voxel as changed.
+    pub fn mark(&mut self, index: Index) {
+        self.0.insert(ChunkIndex::new_encompassing(index));
+        for neighbour in index.iter_neighbours6() {
+            self.0.insert(ChunkIndex::new_encompassing(neighbour));
+        }
+    }
+
+    /// Marks all voxels of the cuboid as changed.
+    pub fn mark_cuboid(&mut self, offset: Index, dimensions: [usize; 3]) {
+        // Extended by one voxel for the neighbours.
+        let start = ChunkIndex::new_encompassing(
+            [offset.x() - 1, offset.y() - 1, offset.z() - 1].into()
+        ).get_world_offset();
+        let end = offset + VoxelUnits(usize_to_i32_arr(dimensions).into());
+        let step = <ChunkShape as ConstShape<3>>::ARRAY;
+        for x in (start.x()..=end.x()).step_by(step[0] as usize) {
+            for y in (start.y()..=end.y()).step_by(step[1] as usize) {
+                for z in (start.z()..=end.z()).step_by(step[2] as usize) {
+                    self.0.insert(ChunkIndex::new_encompassing([x, y, z].into()));
+                }
+            }
+        }
+    }
+
+    /// Marks voxels which differ between the spaces.
+    /// Only voxels of `a` get compared.
+    pub fn from_difference<A, B, V>(a: &A, b: &B) -> Self
+        where
+        A: Space<Voxel=V> + IterableSpace,
+        B: Space<Voxel=V>,
+        V: PartialEq,
+    {
+        let mut dirty = Self::new();
+        a.visit_indices(|index| {
+            if a.get(index) != b.get(index) {
+                dirty.mark(index);
+            }
+        });
+        dirty
+    }
+
+    pub fn extend(&mut self, other: DirtyChunks) {
+        self.0.extend(other.0);
+    }
+
+    pub fn contains(&self, chunk: ChunkIndex) -> bool {
+        self.0.contains(&chunk)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=ChunkIndex> + 'a {
+        self.0.iter().cloned()
+    }
+
+    /// Returns the marked chunks, leaving nothing marked.
+    pub fn take(&mut self) -> DirtyChunks {
+        mem::take(self)
+    }
+}
+
+/// Chunks which a `Cow` can be laid over.
+pub trait ChunkStore {
+    /// Voxels of a single chunk, indexed from the corner of the chunk.
+    type Chunk: Space + MutChunk + Clone + Default;
+    /// Missing chunks are full of default voxels.
+    fn get_chunk_ref(&self, offset: ChunkIndex) -> Option<&Self::Chunk>;
+    /// Only chunks which are stored.
+    fn get_chunk_indices(&self) -> HashSet<ChunkIndex>;
 }
 
-impl<'a> Cow<'a> {
-    pub fn new(base: &'a World) -> Self {
-        Cow {
-            base,
-            overlaid: Default::default(),
+/// Chunks which an `Overlay` can be applied to.
+pub trait ChunkStoreMut: ChunkStore {
+    fn insert_chunk(&mut self, offset: ChunkIndex, chunk: Self::Chunk);
+    /// Afterwards, the chunk is missing.
+    fn remove_chunk(&mut self, offset: ChunkIndex);
+    /// Remembers changed chunks, e.g. for re-meshing.
+    fn mark_dirty(&mut self, chunks: DirtyChunks);
+}
+
+impl<T: ChunkStore> ChunkStore for &T {
+    type Chunk = T::Chunk;
+    fn get_chunk_ref(&self, offset: ChunkIndex) -> Option<&T::Chunk> {
+        (**self).get_chunk_ref(offset)
+    }
+    fn get_chunk_indices(&self) -> HashSet<ChunkIndex> {
+        (**self).get_chunk_indices()
+    }
+}
+
+impl<T: ChunkStore> ChunkStore for &mut T {
+    type Chunk = T::Chunk;
+    fn get_chunk_ref(&self, offset: ChunkIndex) -> Option<&T::Chunk> {
+        (**self).get_chunk_ref(offset)
+    }
+    fn get_chunk_indices(&self) -> HashSet<ChunkIndex> {
+        (**self).get_chunk_indices()
+    }
+}
+
+impl<T: ChunkStoreMut> ChunkStoreMut for &mut T {
+    fn insert_chunk(&mut self, offset: ChunkIndex, chunk: T::Chunk) {
+        (**self).insert_chunk(offset, chunk)
+    }
+    fn remove_chunk(&mut self, offset: ChunkIndex) {
+        (**self).remove_chunk(offset)
+    }
+    fn mark_dirty(&mut self, chunks: DirtyChunks) {
+        (**self).mark_dirty(chunks)
+    }
+}
+
+/// Voxels of any type in a single chunk.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Chunk<V>(Vec<V>);
+
+impl<V: Default + Clone> Default for Chunk<V> {
+    fn default() -> Self {
+        Chunk(vec![V::default(); <ChunkShape as ConstShape<3>>::SIZE as usize])
+    }
+}
+
+impl<V: Copy> Space for Chunk<V> {
+    type Voxel = V;
+    fn get(&self, offset: Index) -> V {
+        self.0[<ChunkShape as ConstShape<3>>::linearize(to_u32_arr(offset.into())) as usize]
+    }
+}
+
+impl<V: Copy> MutChunk for Chunk<V> {
+    type Voxel = V;
+    fn set(&mut self, offset: Index, value: V) {
+        self.0[<ChunkShape as ConstShape<3>>::linearize(to_u32_arr(offset.into())) as usize] = value;
+    }
+}
+
+impl<V> IterableSpace for Chunk<V> {
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new([0, 0, 0].into(), get_chunk_dimensions())
+    }
+}
+
+impl<V> ChunkSize for Chunk<V> {
+    fn get_size(&self) -> usize {
+        mem::size_of::<Self>() + self.0.len() * mem::size_of::<V>()
+    }
+}
+
+pub(crate) fn get_chunk_dimensions() -> [usize; 3] {
+    let [x, y, z] = <ChunkShape as ConstShape<3>>::ARRAY;
+    [x as usize, y as usize, z as usize]
+}
+
+/// A big, sparse world of any voxels, split into chunks.
+/// Missing chunks are full of default voxels.
+/// Changes go through a `Cow`.
+///
+/// Chunks are stored as `C`, e.g. `compress::CompressedChunk` to save memory.
+#[derive(Clone)]
+pub struct ChunkedWorld<V, C = Chunk<V>> {
+    chunks: HashMap<ChunkIndex, C>,
+    dirty: DirtyChunks,
+    voxel: PhantomData<V>,
+}
+
+impl<V, C> Default for ChunkedWorld<V, C> {
+    fn default() -> Self {
+        ChunkedWorld {
+            chunks: HashMap::new(),
+            dirty: DirtyChunks::new(),
+            voxel: PhantomData,
+        }
+    }
+}
+
+impl<V, C> ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Converts every chunk, e.g. to compress them.
+    pub fn convert<D>(self, f: impl Fn(C) -> D) -> ChunkedWorld<V, D> {
+        ChunkedWorld {
+            chunks: self.chunks.into_iter()
+                .map(|(offset, chunk)| (offset, f(chunk)))
+                .collect(),
+            dirty: self.dirty,
+            voxel: PhantomData,
         }
     }
 
-    fn get(&self, offset: Index) -> PaletteVoxel {
+    /// Missing chunks come back full of default voxels.
+    pub fn get_chunk(&self, offset: ChunkIndex) -> C {
+        self.chunks.get(&offset).cloned().unwrap_or_default()
+    }
+
+    pub fn iter_chunks(&self) -> impl Iterator<Item=(ChunkIndex, &C)> {
+        self.chunks.iter().map(|(offset, chunk)| (*offset, chunk))
+    }
+
+    pub fn iter_chunk_indices<'a>(&'a self) -> impl Iterator<Item=ChunkIndex> + 'a {
+        self.chunks.keys().cloned()
+    }
+
+    /// Returns chunks changed by applied overlays since the last call, and forgets them.
+    pub fn take_dirty(&mut self) -> DirtyChunks {
+        self.dirty.take()
+    }
+
+    pub fn cow(&self) -> Cow<&Self> {
+        Cow::new(self)
+    }
+}
+
+impl<V, C> ChunkStore for ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    type Chunk = C;
+    fn get_chunk_ref(&self, offset: ChunkIndex) -> Option<&C> {
+        self.chunks.get(&offset)
+    }
+    fn get_chunk_indices(&self) -> HashSet<ChunkIndex> {
+        self.iter_chunk_indices().collect()
+    }
+}
+
+impl<V, C> ChunkStoreMut for ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    fn insert_chunk(&mut self, offset: ChunkIndex, chunk: C) {
+        self.chunks.insert(offset, chunk);
+    }
+    fn remove_chunk(&mut self, offset: ChunkIndex) {
+        self.chunks.remove(&offset);
+    }
+    fn mark_dirty(&mut self, chunks: DirtyChunks) {
+        self.dirty.extend(chunks);
+    }
+}
+
+impl<V, C> Space for ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    type Voxel = V;
+    fn get(&self, offset: Index) -> V {
         let ci = ChunkIndex::new_encompassing(offset);
-        self.get_chunk(ci).get(Index::new(ci.get_internal_offset(offset)))
+        match self.chunks.get(&ci) {
+            Some(chunk) => chunk.get(Index::new(ci.get_internal_offset(offset))),
+            None => Default::default(),
+        }
+    }
+}
+
+/// Only chunks which hold anything.
+impl<V, C> IterableSpace for ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    type Indices = ChunkedIndices;
+    fn indices(&self) -> Self::Indices {
+        iter_chunked_indices(self.iter_chunk_indices())
+    }
+}
+
+/// Encloses all stored chunks.
+impl<V, C> Extent for ChunkedWorld<V, C>
+    where
+    V: Copy + Default,
+    C: Space<Voxel=V> + MutChunk<Voxel=V> + Clone + Default,
+{
+    fn get_offset(&self) -> Index {
+        get_chunk_bounds(self.iter_chunk_indices()).0
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        get_chunk_bounds(self.iter_chunk_indices()).1
+    }
+}
+
+/// Offset and dimensions of the cuboid enclosing the chunks.
+/// Empty if there are no chunks.
+fn get_chunk_bounds(chunks: impl Iterator<Item=ChunkIndex>) -> (Index, [usize; 3]) {
+    let dimensions = usize_to_i32_arr(get_chunk_dimensions());
+    let bounds = chunks
+        .map(|chunk| chunk.get_world_offset())
+        .fold(None, |bounds: Option<([i32; 3], [i32; 3])>, offset| {
+            let offset: [i32; 3] = offset.into();
+            let end = [0, 1, 2].map(|i| offset[i] + dimensions[i]);
+            Some(match bounds {
+                None => (offset, end),
+                Some((start, stop)) => (
+                    [0, 1, 2].map(|i| start[i].min(offset[i])),
+                    [0, 1, 2].map(|i| stop[i].max(end[i])),
+                ),
+            })
+        });
+    match bounds {
+        Some((start, end)) => (
+            start.into(),
+            [0, 1, 2].map(|i| (end[i] - start[i]) as usize),
+        ),
+        None => ([0, 0, 0].into(), [0, 0, 0]),
+    }
+}
+
+/// A naive copy-on-write overlay over a world, or over another Cow.
+/// Its changes can be eventually applied to the underlying world.
+///
+/// Over a `&mut` base, it works as a transaction: `commit` or `rollback`.
+/// `begin` starts a nested one.
+pub struct Cow<B: ChunkStore> {
+    base: B,
+    overlaid: HashMap<ChunkIndex, B::Chunk>,
+    /// Chunks of the base which are gone.
+    removed: HashSet<ChunkIndex>,
+    dirty: DirtyChunks,
+}
+
+impl<B: ChunkStore> Cow<B> {
+    pub fn new(base: B) -> Self {
+        Cow {
+            base,
+            overlaid: Default::default(),
+            removed: Default::default(),
+            dirty: DirtyChunks::new(),
+        }
     }
 
     // Not sure if this is the right place to do this, but let's try.
-    pub fn set(&mut self, offset: Index, value: PaletteVoxel) {
+    pub fn set(&mut self, offset: Index, value: <B::Chunk as MutChunk>::Voxel) {
         let ci = ChunkIndex::new_encompassing(offset);
         let i = Index::new(ci.get_internal_offset(offset));
         let chunk = self.get_chunk_mut(ci);
         chunk.set(i, value);
+        self.dirty.mark(offset);
     }
 
-    fn get_chunk(&self, offset: ChunkIndex) -> PaletteIdChunk {
-        *self.overlaid.get(&offset)
-            .unwrap_or(&self.base.get_chunk(offset))
+    fn get_chunk_mut(&mut self, offset: ChunkIndex) -> &mut B::Chunk {
+        let base = if self.removed.remove(&offset) {
+            None
+        } else {
+            self.base.get_chunk_ref(offset)
+        };
+        self.overlaid.entry(offset)
+            .or_insert_with(|| base.cloned().unwrap_or_default())
     }
 
-    fn get_chunk_ref(&self, offset: ChunkIndex) -> &PaletteIdChunk {
-        self.overlaid.get(&offset)
-            .unwrap_or(self.base.get_chunk_ref(offset))
+    fn set_chunk(&mut self, offset: ChunkIndex, chunk: B::Chunk) {
+        self.insert_chunk(offset, chunk);
+        self.dirty.mark_cuboid(offset.get_world_offset(), get_chunk_dimensions());
     }
 
-    fn get_chunk_mut(&mut self, offset: ChunkIndex) -> &mut PaletteIdChunk {
-        self.overlaid.entry(offset)
-            .or_insert_with(|| self.base.get_chunk(offset))
+    /* Nice idea, but we need to implement a struct that will hold the overlaid while it's being drained.
+    fn iter_overlay(self) -> impl Iterator<Item=(ChunkIndex, PaletteIdChunk)> {
+        self.overlaid.drain()
+    }*/
+
+    /// Chunks changed so far.
+    pub fn get_dirty(&self) -> &DirtyChunks {
+        &self.dirty
     }
 
-    fn set_chunk(&mut self, offset: ChunkIndex, chunk: PaletteIdChunk) {
-        self.overlaid.insert(offset, chunk);
+    /// Extracts changes ready for application on a mutable world
+    pub fn into_changes(self) -> Overlay<B::Chunk> {
+        Overlay {
+            chunks: self.overlaid,
+            removed: self.removed,
+            dirty: self.dirty,
+        }
     }
 
-    fn iter_chunks(&self) -> impl Iterator<Item=(ChunkIndex, &PaletteIdChunk)> {
-        self.iter_chunk_indices()
-            .map(|offset| (offset, self.get_chunk_ref(offset)))
+    /// Starts a nested transaction.
+    /// Its changes show up here only after it commits.
+    pub fn begin(&mut self) -> Cow<&mut Self> {
+        Cow::new(self)
     }
 
-    fn iter_chunk_indices<'b>(&'b self) -> impl Iterator<Item=ChunkIndex> + 'b {
-        self.base.chunks.keys()
-            .filter(|offset| !self.overlaid.contains_key(offset))
-            .chain(self.overlaid.keys())
+    /// Forgets all changes, leaving the base as it was.
+    pub fn rollback(self) {}
+}
+
+impl<B: ChunkStoreMut> Cow<B> {
+    /// Applies all changes to the base.
+    pub fn commit(self) {
+        let mut base = self.base;
+        Overlay {
+            chunks: self.overlaid,
+            removed: self.removed,
+            dirty: self.dirty,
+        }.apply_without_inverse(&mut base);
+    }
+}
+
+impl<B: ChunkStore> ChunkStore for Cow<B> {
+    type Chunk = B::Chunk;
+    fn get_chunk_ref(&self, offset: ChunkIndex) -> Option<&B::Chunk> {
+        if self.removed.contains(&offset) {
+            return None;
+        }
+        self.overlaid.get(&offset)
+            .or_else(|| self.base.get_chunk_ref(offset))
+    }
+    fn get_chunk_indices(&self) -> HashSet<ChunkIndex> {
+        let mut indices: HashSet<_> = self.base.get_chunk_indices()
+            .difference(&self.removed)
             .cloned()
+            .collect();
+        indices.extend(self.overlaid.keys().cloned());
+        indices
     }
+}
 
-    /* Nice idea, but we need to implement a struct that will hold the overlaid while it's being drained.
-    fn iter_overlay(self) -> impl Iterator<Item=(ChunkIndex, PaletteIdChunk)> {
-        self.overlaid.drain()
-    }*/
+impl<B: ChunkStore> ChunkStoreMut for Cow<B> {
+    fn insert_chunk(&mut self, offset: ChunkIndex, chunk: B::Chunk) {
+        self.removed.remove(&offset);
+        self.overlaid.insert(offset, chunk);
+    }
+    fn remove_chunk(&mut self, offset: ChunkIndex) {
+        self.overlaid.remove(&offset);
+        self.removed.insert(offset);
+    }
+    fn mark_dirty(&mut self, chunks: DirtyChunks) {
+        self.dirty.extend(chunks);
+    }
+}
 
-    /// Extracts changes ready for application on a mutable world
-    pub fn into_changes(self) -> Overlay {
-        Overlay(self.overlaid)
+impl<B> Space for Cow<B>
+    where
+    B: ChunkStore,
+    <B::Chunk as Space>::Voxel: Default,
+{
+    type Voxel = <B::Chunk as Space>::Voxel;
+    fn get(&self, offset: Index) -> Self::Voxel {
+        let ci = ChunkIndex::new_encompassing(offset);
+        match self.get_chunk_ref(ci) {
+            Some(chunk) => chunk.get(Index::new(ci.get_internal_offset(offset))),
+            None => Default::default(),
+        }
+    }
+}
+
+impl<B: ChunkStore> MutChunk for Cow<B> {
+    type Voxel = <B::Chunk as MutChunk>::Voxel;
+    fn set(&mut self, offset: Index, value: Self::Voxel) {
+        Cow::set(self, offset, value)
     }
 }
 
-pub struct Overlay(HashMap<ChunkIndex, PaletteIdChunk>);
+/// Only chunks which hold anything.
+impl<B: ChunkStore> IterableSpace for Cow<B> {
+    type Indices = ChunkedIndices;
+    fn indices(&self) -> Self::Indices {
+        iter_chunked_indices(self.get_chunk_indices().into_iter())
+    }
+}
+
+/// Encloses all chunks of the base and of the overlay.
+impl<B: ChunkStore> Extent for Cow<B> {
+    fn get_offset(&self) -> Index {
+        get_chunk_bounds(self.get_chunk_indices().into_iter()).0
+    }
+    fn get_dimensions(&self) -> [usize; 3] {
+        get_chunk_bounds(self.get_chunk_indices().into_iter()).1
+    }
+}
+
+/// Indices of voxels in whole chunks.
+pub type ChunkedIndices = iter::Flatten<vec::IntoIter<CuboidIndices>>;
+
+pub(crate) fn iter_chunked_indices(chunks: impl Iterator<Item=ChunkIndex>) -> ChunkedIndices {
+    chunks
+        .map(|chunk| CuboidIndices::new(chunk.get_world_offset(), get_chunk_dimensions()))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+}
+
+/// Changes to a world, which can be applied later.
+/// Serializes into a patch.
+pub struct Overlay<C = PaletteIdChunk> {
+    pub(crate) chunks: HashMap<ChunkIndex, C>,
+    /// Chunks which the changes delete.
+    pub(crate) removed: HashSet<ChunkIndex>,
+    pub(crate) dirty: DirtyChunks,
+}
+
+impl<C: Space + Clone + Default> Overlay<C> {
+    /// Chunks which the changes touch.
+    pub fn get_dirty(&self) -> &DirtyChunks {
+        &self.dirty
+    }
 
-impl Overlay {
     /// Applies changes to world. Caution: does not care if it applies to the correct world.
-    pub fn apply(mut self, output: &mut World) {
-        for (offset, chunk) in self.0.drain() {
-            output.chunks.insert(offset, chunk);
+    /// The world remembers the changed chunks until `World::take_dirty`.
+    ///
+    /// Returns the inverse: the replaced and removed chunks.
+    /// Chunks which were missing get removed by it.
+    pub fn apply<T: ChunkStoreMut<Chunk=C>>(self, output: &mut T) -> Overlay<C> {
+        let mut replaced = HashMap::with_capacity(self.chunks.len());
+        let mut missing = HashSet::new();
+        for (offset, chunk) in self.chunks {
+            match output.get_chunk_ref(offset) {
+                Some(old) => { replaced.insert(offset, old.clone()); },
+                None => { missing.insert(offset); },
+            }
+            output.insert_chunk(offset, chunk);
+        }
+        for offset in self.removed {
+            if let Some(old) = output.get_chunk_ref(offset) {
+                replaced.insert(offset, old.clone());
+                output.remove_chunk(offset);
+            }
+        }
+        output.mark_dirty(self.dirty.clone());
+        Overlay {
+            chunks: replaced,
+            removed: missing,
+            dirty: self.dirty,
         }
     }
+
+    /// Like `apply`, but doesn't copy the replaced chunks.
+    pub(crate) fn apply_without_inverse<T: ChunkStoreMut<Chunk=C>>(self, output: &mut T) {
+        for (offset, chunk) in self.chunks {
+            output.insert_chunk(offset, chunk);
+        }
+        for offset in self.removed {
+            output.remove_chunk(offset);
+        }
+        output.mark_dirty(self.dirty);
+    }
 }
 
-use std::marker::PhantomData;
+impl<C: ChunkSize> Overlay<C> {
+    /// Memory taken by the chunks.
+    pub fn get_size(&self) -> usize {
+        self.chunks.values().map(ChunkSize::get_size).sum()
+    }
+}
+
+/// Applied overlays, to be undone and redone.
+/// The oldest ones get forgotten when they take more than the memory cap.
+pub struct UndoStack<C = PaletteIdChunk> {
+    undo_queue: VecDeque<Overlay<C>>,
+    redo_queue: VecDeque<Overlay<C>>,
+    max_size: usize,
+}
+
+impl<C: Space + ChunkSize + Clone + Default> UndoStack<C> {
+    /// `max_size` is in bytes.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            undo_queue: Default::default(),
+            redo_queue: Default::default(),
+            max_size,
+        }
+    }
+
+    /// Applies the changes, remembering how to undo them.
+    pub fn apply<T: ChunkStoreMut<Chunk=C>>(&mut self, changes: Overlay<C>, output: &mut T) {
+        let inverse = changes.apply(output);
+        self.undo_queue.push_back(inverse);
+        // We don't want to keep "undone edits" before this new one.
+        self.redo_queue.clear();
+        self.forget_oldest();
+    }
+
+    /// Returns false if there was nothing to undo.
+    pub fn undo<T: ChunkStoreMut<Chunk=C>>(&mut self, output: &mut T) -> bool {
+        reapply(&mut self.undo_queue, &mut self.redo_queue, output)
+    }
+
+    /// Returns false if there was nothing to redo.
+    pub fn redo<T: ChunkStoreMut<Chunk=C>>(&mut self, output: &mut T) -> bool {
+        reapply(&mut self.redo_queue, &mut self.undo_queue, output)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_queue.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_queue.is_empty()
+    }
+
+    /// Memory taken by all stored overlays.
+    pub fn get_size(&self) -> usize {
+        self.undo_queue.iter()
+            .chain(self.redo_queue.iter())
+            .map(Overlay::get_size)
+            .sum()
+    }
+
+    fn forget_oldest(&mut self) {
+        let mut size = self.get_size();
+        while size > self.max_size {
+            match self.undo_queue.pop_front() {
+                Some(overlay) => size -= overlay.get_size(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Moves the newest overlay to the other queue, inverting it on the way.
+fn reapply<C, T>(
+    from: &mut VecDeque<Overlay<C>>,
+    to: &mut VecDeque<Overlay<C>>,
+    output: &mut T,
+) -> bool
+    where
+    C: Space + Clone + Default,
+    T: ChunkStoreMut<Chunk=C>,
+{
+    match from.pop_back() {
+        Some(overlay) => {
+            to.push_back(overlay.apply(output));
+            true
+        },
+        None => false,
+    }
+}
+
+/// A voxel which both sides of a merge changed, each in a different way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Conflict<V = PaletteVoxel> {
+    pub index: Index,
+    pub base: V,
+    pub ours: V,
+    pub theirs: V,
+}
+
+/// The outcome of `merge`.
+pub struct Merge<C: Space = PaletteIdChunk> {
+    /// Contains changes from both sides.
+    /// Where they conflict, our voxel stays.
+    pub overlay: Overlay<C>,
+    /// Sorted by index, x first.
+    pub conflicts: Vec<Conflict<C::Voxel>>,
+}
+
+/// Three-way merge of two overlays made over the same base.
+/// Unlike applying them one after the other,
+/// changes to different voxels of the same chunk both survive.
+/// A chunk which one side removes and the other changes stays changed.
+pub fn merge<B, C>(base: &B, ours: &Overlay<C>, theirs: &Overlay<C>) -> Merge<C>
+    where
+    B: ChunkStore<Chunk=C>,
+    C: Space + MutChunk<Voxel=<C as Space>::Voxel> + Clone + Default,
+    <C as Space>::Voxel: PartialEq,
+{
+    let mut chunks = HashMap::new();
+    let mut conflicts = Vec::new();
+    let offsets: HashSet<_> = ours.chunks.keys().chain(theirs.chunks.keys()).cloned().collect();
+    for offset in offsets {
+        let chunk = match (ours.chunks.get(&offset), theirs.chunks.get(&offset)) {
+            (Some(ours), None) => ours.clone(),
+            (None, Some(theirs)) => theirs.clone(),
+            (Some(ours), Some(theirs)) => {
+                let original = base.get_chunk_ref(offset).cloned().unwrap_or_default();
+                let mut merged = ours.clone();
+                for i in CuboidIndices::new([0, 0, 0].into(), get_chunk_dimensions()) {
+                    let (b, o, t) = (original.get(i), ours.get(i), theirs.get(i));
+                    if o == b {
+                        merged.set(i, t);
+                    } else if t != b && t != o {
+                        conflicts.push(Conflict {
+                            index: offset.get_world_offset() + VoxelUnits(i.0),
+                            base: b,
+                            ours: o,
+                            theirs: t,
+                        });
+                    }
+                }
+                merged
+            },
+            (None, None) => unreachable!(),
+        };
+        chunks.insert(offset, chunk);
+    }
+    conflicts.sort_by_key(|conflict| -> [i32; 3] { conflict.index.into() });
+    let removed = ours.removed.union(&theirs.removed)
+        .filter(|offset| !chunks.contains_key(offset))
+        .cloned()
+        .collect();
+    let mut dirty = ours.dirty.clone();
+    dirty.extend(theirs.dirty.clone());
+    Merge {
+        overlay: Overlay { chunks, removed, dirty },
+        conflicts,
+    }
+}
 
 pub struct View<'a, S, Shape> {
     world: &'a S,
@@ -145,8 +783,10 @@ impl<'a, S: Space, Shape> Space for View<'a, S, Shape> {
 }
 
 impl<'a, S: IterableSpace, Shape> IterableSpace for View<'a, S, Shape> {
-    fn visit_indices<F: FnMut(Index)>(&self, mut f: F) {
-        self.world.visit_indices(|i| f(i - VoxelUnits(self.offset.into())))
+    type Indices = ShiftedIndices<S::Indices>;
+    fn indices(&self) -> Self::Indices {
+        let offset = self.offset;
+        ShiftedIndices::new(self.world.indices(), [-offset.x(), -offset.y(), -offset.z()])
     }
 }
 
@@ -163,11 +803,20 @@ impl<'a, S, Shape> Extent for View<'a, S, Shape>
 
 /// Flat 3d array, out-of-bounds gives default voxel.
 /// This should be pretty fast, but not suitable for any large space.
-#[derive(Clone, PartialEq, Debug)]
+/// Remembers which chunks changed, starting with all of them.
+#[derive(Clone, Debug)]
 pub struct FlatPaddedGridCuboid<V, Shape: ConstShape<3>> {
     pub(crate) data: Vec<V>,
     pub(crate) offset: Index,
     shape: PhantomData<Shape>,
+    dirty: DirtyChunks,
+}
+
+/// Ignores which chunks changed.
+impl<V: PartialEq, Shape: ConstShape<3>> PartialEq for FlatPaddedGridCuboid<V, Shape> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset && self.data == other.data
+    }
 }
 
 /* Const expressions not in stable yet
@@ -188,6 +837,7 @@ impl<V: Default + Copy, Shape: ConstShape<3, Coord=usize>> FlatPaddedGridCuboid<
             data,
             offset,
             shape: Default::default(),
+            dirty: Self::all_dirty(offset),
         }
     }
     /// Dimensions are determined by the compile-time Shape.
@@ -203,6 +853,7 @@ impl<V: Default + Copy, Shape: ConstShape<3, Coord=usize>> FlatPaddedGridCuboid<
             data,
             offset,
             shape: Default::default(),
+            dirty: Self::all_dirty(offset),
         }
     }
 
@@ -226,6 +877,7 @@ impl<V: Default + Copy, Shape: ConstShape<3, Coord=usize>> FlatPaddedGridCuboid<
         if self.contains(index) {
             let offset = index - VoxelUnits(self.offset.0);
             self.data[Shape::linearize(to_usize_arr(offset.into()))] = value;
+            self.dirty.mark(index);
             Ok(())
         } else {
             Err(OutOfBounds)
@@ -244,8 +896,26 @@ impl<V, Shape: ConstShape<3, Coord=usize>> FlatPaddedGridCuboid<V, Shape> {
             data: samples,
             offset,
             shape: Default::default(),
+            dirty: Self::all_dirty(offset),
         }
     }
+
+    fn all_dirty(offset: Index) -> DirtyChunks {
+        let mut dirty = DirtyChunks::new();
+        dirty.mark_cuboid(offset, Shape::ARRAY);
+        dirty
+    }
+
+    /// Returns chunks changed since the last call, and forgets them.
+    pub fn take_dirty(&mut self) -> DirtyChunks {
+        self.dirty.take()
+    }
+
+    /// Marks more chunks as changed,
+    /// e.g. ones which used to be covered before being replaced.
+    pub fn mark_dirty(&mut self, chunks: DirtyChunks) {
+        self.dirty.extend(chunks);
+    }
 }
 
 impl<V, Shape> Space for FlatPaddedGridCuboid<V, Shape>
@@ -269,12 +939,9 @@ impl<V, Shape> IterableSpace for FlatPaddedGridCuboid<V, Shape>
     V: Copy,
     Shape: ConstShape<3, Coord=usize>,
 {
-    fn visit_indices<F: FnMut(Index)>(&self, mut f: F) {
-        for i in 0..Shape::SIZE {
-            let idx = <Shape as ConstShape<3>>::delinearize(i);
-            let idx: Index = usize_to_i32_arr(idx).into();
-            f(idx + VoxelUnits(self.offset.0.into()))
-        }
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new(self.offset, Shape::ARRAY)
     }
 }
 
@@ -303,12 +970,28 @@ impl<V, S, Shape> From<S> for FlatPaddedGridCuboid<V, Shape>
 
 /// Flat 3d array, out-of-bounds gives default voxel.
 /// This should be pretty fast, but not suitable for any large space.
+/// Unlike `FlatPaddedGridCuboid`, dimensions are chosen at runtime.
+#[derive(Clone, PartialEq, Debug)]
 pub struct FlatPaddedCuboid<V> {
     data: Vec<V>,
     offset: Index,
     dimensions: [usize; 3],
 }
 
+impl<V: Default + Clone> FlatPaddedCuboid<V> {
+    /// Creates a new one filled with emptiness
+    pub fn new(offset: Index, dimensions: [usize; 3]) -> Self {
+        let shape = RuntimeShape::<usize, 3>::new(dimensions);
+        let mut data = Vec::with_capacity(shape.size());
+        data.resize(shape.size(), V::default());
+        Self {
+            data,
+            offset,
+            dimensions,
+        }
+    }
+}
+
 impl<V: Default> FlatPaddedCuboid<V> {
     /// Offset is the lowest point of this cuboid portion.
     pub fn new_from_space<S>(space: &S, offset: Index, dimensions: [usize; 3]) -> Self
@@ -332,6 +1015,11 @@ impl<V: Default> FlatPaddedCuboid<V> {
         RuntimeShape::<usize, 3>::new(self.dimensions)
     }
 
+    /// Caution, samples are aligned according to the runtime shape.
+    pub fn get_samples(&self) -> &[V] {
+        self.data.as_ref()
+    }
+
     /// Returns the index that's actuallly the corner, e.g. not 1 unit beyond
     fn opposite_corner(&self) -> Index {
         self.offset
@@ -350,9 +1038,10 @@ impl<V: Default> FlatPaddedCuboid<V> {
         true
     }
 
-    fn set(&mut self, index: Index, value: V) -> Result<(), OutOfBounds> {
+    pub fn set(&mut self, index: Index, value: V) -> Result<(), OutOfBounds> {
         if self.contains(index) {
-            let i = self.get_shape().linearize(to_usize_arr(index.into()));
+            let offset = index - VoxelUnits(self.offset.0);
+            let i = self.get_shape().linearize(to_usize_arr(offset.into()));
             self.data[i] = value;
             Ok(())
         } else {
@@ -365,8 +1054,9 @@ impl<V> Space for FlatPaddedCuboid<V>
     where V: Default + Copy,
 {
     type Voxel = V;
-    fn get(&self, offset: Index) -> Self::Voxel {
-        if self.contains(offset) {
+    fn get(&self, index: Index) -> Self::Voxel {
+        if self.contains(index) {
+            let offset = index - VoxelUnits(self.offset.0);
             self.data[self.get_shape().linearize(to_usize_arr(offset.into()))]
         } else {
             Default::default()
@@ -377,13 +1067,9 @@ impl<V> Space for FlatPaddedCuboid<V>
 impl<V> IterableSpace for FlatPaddedCuboid<V>
     where V: Default + Copy
 {
-    fn visit_indices<F: FnMut(Index)>(&self, mut f: F) {
-        let shape = self.get_shape();
-        for i in 0..(shape.size()) {
-            let idx = shape.delinearize(i);
-            let idx: Index = usize_to_i32_arr(idx).into();
-            f(idx + VoxelUnits(self.offset.0.into()))
-        }
+    type Indices = CuboidIndices;
+    fn indices(&self) -> Self::Indices {
+        CuboidIndices::new(self.offset, self.dimensions)
     }
 }
 
@@ -422,10 +1108,27 @@ impl<E, F> IntoCuboid for Zip<E, F>{}
 
 impl<'a, S, Shape> IntoCuboid for View<'a, S, Shape> {}
 
+impl<T> IntoCuboid for ZipAll<T> {}
+
+impl<S> IntoCuboid for Translate<S> {}
+
+impl<S> IntoCuboid for Crop<S> {}
+
+impl<S> IntoCuboid for Pad<S> {}
+
+impl<S> IntoCuboid for Reoriented<S> {}
+
+impl<S> IntoCuboid for Resampled<S> {}
+
+impl<V, C> IntoCuboid for ChunkedWorld<V, C> {}
+
+impl<B: ChunkStore> IntoCuboid for Cow<B> {}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    use crate::prefab::World;
     use crate::re::ConstPow2Shape;
 
     #[test]
@@ -451,6 +1154,285 @@ mod test {
         assert_eq!(view.get([1, 1, 1].into()), PaletteVoxel(1));
     }
 
+    #[test]
+    fn dirty_neighbours() {
+        let mut dirty = DirtyChunks::new();
+        dirty.mark([5, 5, 5].into());
+        assert_eq!(dirty.iter().count(), 1);
+        // On the face of the chunk.
+        dirty.mark([0, 5, 5].into());
+        assert!(dirty.contains(ChunkIndex::new_encompassing([-1, 5, 5].into())));
+        assert_eq!(dirty.iter().count(), 2);
+    }
+
+    #[test]
+    fn dirty_cuboid() {
+        let mut dirty = DirtyChunks::new();
+        dirty.mark_cuboid([0, -8, 0].into(), [32, 32, 32]);
+        // Padded by one voxel: 4 chunks along x and z, 3 along y.
+        assert_eq!(dirty.iter().count(), 4 * 3 * 4);
+        assert!(dirty.contains(ChunkIndex::new_encompassing([-1, -9, -1].into())));
+        assert!(dirty.contains(ChunkIndex::new_encompassing([32, 24, 32].into())));
+    }
+
+    #[test]
+    fn cow_dirty() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([3, 3, 3].into(), PaletteVoxel(1));
+        let changes = cow.into_changes();
+        changes.apply(&mut world);
+        let dirty = world.take_dirty();
+        assert!(dirty.contains(ChunkIndex::new_encompassing([3, 3, 3].into())));
+        assert_eq!(dirty.iter().count(), 1);
+        assert!(world.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn cow_space() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([1, 2, 3].into(), PaletteVoxel(1));
+        assert_eq!(cow.get([1, 2, 3].into()), PaletteVoxel(1));
+        assert_eq!(cow.indices().count(), 4096);
+        cow.into_changes().apply(&mut world);
+
+        let mut cow = Cow::new(&world);
+        cow.set([-1, 2, 3].into(), PaletteVoxel(2));
+        // The old chunk and the new one.
+        assert_eq!(cow.indices().count(), 2 * 4096);
+        let solid: Vec<_> = cow.voxels()
+            .filter(|(_, v)| *v != PaletteVoxel::EMPTY)
+            .collect();
+        assert_eq!(solid.len(), 2);
+    }
+
+    #[test]
+    fn cow_nested_commit() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&mut world);
+        cow.set([0, 0, 0].into(), PaletteVoxel(1));
+        {
+            let mut stroke = cow.begin();
+            stroke.set([1, 0, 0].into(), PaletteVoxel(2));
+            assert_eq!(stroke.get([0, 0, 0].into()), PaletteVoxel(1));
+            stroke.commit();
+        }
+        assert_eq!(cow.get([1, 0, 0].into()), PaletteVoxel(2));
+        assert!(!cow.get_dirty().is_empty());
+        cow.commit();
+        assert_eq!(world.get([0, 0, 0].into()), PaletteVoxel(1));
+        assert_eq!(world.get([1, 0, 0].into()), PaletteVoxel(2));
+        assert!(!world.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn cow_nested_rollback() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&mut world);
+        cow.set([5, 5, 5].into(), PaletteVoxel(1));
+        {
+            let mut stroke = cow.begin();
+            stroke.set([5, 5, 5].into(), PaletteVoxel(2));
+            stroke.set([40, 5, 5].into(), PaletteVoxel(2));
+            stroke.rollback();
+        }
+        assert_eq!(cow.get([5, 5, 5].into()), PaletteVoxel(1));
+        assert_eq!(cow.get([40, 5, 5].into()), PaletteVoxel::EMPTY);
+        assert_eq!(cow.get_dirty().iter().count(), 1);
+        cow.rollback();
+        assert_eq!(world.get([5, 5, 5].into()), PaletteVoxel::EMPTY);
+        assert!(world.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn overlay_inverse() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(1));
+        cow.into_changes().apply(&mut world);
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(2));
+        let inverse = cow.into_changes().apply(&mut world);
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(2));
+        inverse.apply(&mut world);
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut world = World::default();
+        let mut stack = UndoStack::new(usize::MAX);
+        for value in 1..=2 {
+            let mut cow = Cow::new(&world);
+            cow.set([1, 1, 1].into(), PaletteVoxel(value));
+            let changes = cow.into_changes();
+            stack.apply(changes, &mut world);
+        }
+        assert!(stack.undo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+        assert!(stack.undo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel::EMPTY);
+        assert!(!stack.undo(&mut world));
+        assert!(stack.redo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+
+        // A new edit forgets what was undone.
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(3));
+        let changes = cow.into_changes();
+        stack.apply(changes, &mut world);
+        assert!(!stack.redo(&mut world));
+        assert!(stack.undo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+    }
+
+    #[test]
+    fn undo_memory_cap() {
+        let mut world = World::default();
+        // The chunk exists already, so every inverse holds a copy of it.
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(1));
+        cow.into_changes().apply(&mut world);
+        // Room for two single-chunk edits.
+        let mut stack = UndoStack::new(2 * mem::size_of::<PaletteIdChunk>());
+        for value in 2..=4 {
+            let mut cow = Cow::new(&world);
+            cow.set([1, 1, 1].into(), PaletteVoxel(value));
+            let changes = cow.into_changes();
+            stack.apply(changes, &mut world);
+        }
+        assert!(stack.undo(&mut world));
+        assert!(stack.undo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(2));
+        assert!(!stack.can_undo());
+    }
+
+    #[test]
+    fn undo_removes_new_chunks() {
+        let mut world = ChunkedWorld::<u8>::new();
+        let mut stack = UndoStack::new(usize::MAX);
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), 3);
+        let changes = cow.into_changes();
+        stack.apply(changes, &mut world);
+        assert_eq!(world.indices().count(), 4096);
+        assert!(stack.undo(&mut world));
+        assert_eq!(world.indices().count(), 0);
+        assert!(stack.redo(&mut world));
+        assert_eq!(world.get([1, 1, 1].into()), 3);
+    }
+
+    #[test]
+    fn cow_remove_chunk() {
+        let mut world = World::default();
+        let mut cow = Cow::new(&world);
+        cow.set([1, 1, 1].into(), PaletteVoxel(1));
+        cow.into_changes().apply(&mut world);
+        let mut cow = Cow::new(&mut world);
+        cow.remove_chunk(ChunkIndex::new_encompassing([1, 1, 1].into()));
+        assert_eq!(cow.indices().count(), 0);
+        assert_eq!(cow.get([1, 1, 1].into()), PaletteVoxel::EMPTY);
+        cow.commit();
+        assert_eq!(world.indices().count(), 0);
+    }
+
+    fn edit(world: &World, voxels: &[([i32; 3], u8)]) -> Overlay {
+        let mut cow = Cow::new(world);
+        for (index, value) in voxels {
+            cow.set((*index).into(), PaletteVoxel(*value));
+        }
+        cow.into_changes()
+    }
+
+    #[test]
+    fn merge_same_chunk() {
+        let mut world = World::default();
+        edit(&world, &[([1, 1, 1], 1)]).apply(&mut world);
+        let ours = edit(&world, &[([2, 2, 2], 2)]);
+        let theirs = edit(&world, &[([3, 3, 3], 3), ([40, 0, 0], 3)]);
+        let merged = merge(&world, &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        merged.overlay.apply(&mut world);
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+        assert_eq!(world.get([2, 2, 2].into()), PaletteVoxel(2));
+        assert_eq!(world.get([3, 3, 3].into()), PaletteVoxel(3));
+        assert_eq!(world.get([40, 0, 0].into()), PaletteVoxel(3));
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let mut world = World::default();
+        let ours = edit(&world, &[([1, 1, 1], 1), ([2, 2, 2], 4)]);
+        let theirs = edit(&world, &[([1, 1, 1], 2), ([2, 2, 2], 4)]);
+        let merged = merge(&world, &ours, &theirs);
+        assert_eq!(
+            merged.conflicts,
+            vec![Conflict {
+                index: [1, 1, 1].into(),
+                base: PaletteVoxel::EMPTY,
+                ours: PaletteVoxel(1),
+                theirs: PaletteVoxel(2),
+            }],
+        );
+        merged.overlay.apply(&mut world);
+        assert_eq!(world.get([1, 1, 1].into()), PaletteVoxel(1));
+        assert_eq!(world.get([2, 2, 2].into()), PaletteVoxel(4));
+    }
+
+    #[test]
+    fn merge_conflicts_sorted() {
+        let world = World::default();
+        let indices = [[40, 0, 0], [-20, 5, 0], [1, 1, 1], [1, -30, 1], [20, 20, 20]];
+        let ours: Vec<_> = indices.iter().map(|i| (*i, 1)).collect();
+        let theirs: Vec<_> = indices.iter().map(|i| (*i, 2)).collect();
+        let merged = merge(&world, &edit(&world, &ours), &edit(&world, &theirs));
+        let found: Vec<[i32; 3]> = merged.conflicts.iter().map(|c| c.index.into()).collect();
+        assert_eq!(found, vec![[-20, 5, 0], [1, -30, 1], [1, 1, 1], [20, 20, 20], [40, 0, 0]]);
+    }
+
+    #[test]
+    fn chunked_world() {
+        let mut world = ChunkedWorld::<f32>::new();
+        assert_eq!(world.get_dimensions(), [0, 0, 0]);
+        let mut cow = Cow::new(&mut world);
+        cow.set([-1, 2, 3].into(), 0.5);
+        cow.set([20, 2, 3].into(), 1.5);
+        assert_eq!(cow.get([-1, 2, 3].into()), 0.5);
+        assert_eq!(cow.get_offset(), [-16, 0, 0].into());
+        cow.commit();
+        assert_eq!(world.get([20, 2, 3].into()), 1.5);
+        assert_eq!(world.get([100, 2, 3].into()), 0.0);
+        assert_eq!(world.get_offset(), [-16, 0, 0].into());
+        assert_eq!(world.get_dimensions(), [48, 16, 16]);
+        assert_eq!(world.indices().count(), 2 * 4096);
+        assert!(!world.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn chunked_world_flatten() {
+        let mut world = ChunkedWorld::<u16>::new();
+        let mut cow = Cow::new(&mut world);
+        cow.set([1, 2, 3].into(), 7);
+        cow.commit();
+        let flat: FlatPaddedCuboid<u16> = world.into();
+        assert_eq!(flat.get_dimensions(), [16, 16, 16]);
+        assert_eq!(flat.get([1, 2, 3].into()), 7);
+    }
+
+    #[test]
+    fn cuboid_dirty() {
+        type Cuboid<V> = FlatPaddedGridCuboid<V, ConstPow2Shape<5, 5, 5>>;
+        let mut extent = Cuboid::<bool>::new([0, 0, 0].into());
+        assert!(!extent.take_dirty().is_empty());
+        extent.set([20, 20, 20].into(), true).unwrap();
+        assert_eq!(
+            extent.take_dirty().iter().collect::<Vec<_>>(),
+            vec![ChunkIndex::new_encompassing([20, 20, 20].into())],
+        );
+        assert!(extent.take_dirty().is_empty());
+    }
+
     #[test]
     fn copy_offset() {
         type Cuboid<V> = FlatPaddedGridCuboid<V, ConstPow2Shape<5, 5, 5>>;
@@ -465,4 +1447,13 @@ mod test {
         let mut extent = Cuboid::<bool>::new([0, -10, 0].into());
         assert!(extent.set([0, -9, 0].into(), true).is_ok());
     }
+
+    #[test]
+    fn runtime_set_offset() {
+        let mut extent = FlatPaddedCuboid::<bool>::new([0, -10, 0].into(), [3, 4, 5]);
+        assert!(extent.set([2, -7, 4].into(), true).is_ok());
+        assert!(extent.set([2, -6, 4].into(), true).is_err());
+        assert_eq!(extent.get([2, -7, 4].into()), true);
+        assert_eq!(extent.get([0, -10, 0].into()), false);
+    }
 }
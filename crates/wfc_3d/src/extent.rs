@@ -4,7 +4,7 @@
 
 use baustein::indices::{usize_to_i32_arr, to_usize_arr, Index, VoxelUnits};
 use baustein::re::{ConstShape, RuntimeShape, Shape};
-use baustein::world::FlatPaddedGridCuboid;
+use baustein::world::{FlatPaddedCuboid, FlatPaddedGridCuboid};
 use std::cmp;
 
 // used traits
@@ -109,6 +109,16 @@ impl<V, Shape> Stamped for FlatPaddedGridCuboid<V, Shape>
     }
 }
 
+impl<V> Stamped for FlatPaddedCuboid<V> {
+    fn get_stamps_extent<StampShape: ConstShape>(&self) -> Extent {
+        Extent::new(
+            self.get_offset(),
+            self.get_beyond_opposite_corner()
+                - VoxelUnits(usize_to_i32_arr(<StampShape as ConstShape>::ARRAY))
+                + VoxelUnits([1, 1, 1].into()),
+        )
+    }
+}
 
 impl Stamped for Extent {
     fn get_stamps_extent<StampShape: ConstShape>(&self) -> Extent {
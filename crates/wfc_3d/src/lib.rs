@@ -9,16 +9,18 @@ pub mod stamp;
 pub mod wave;
 
 use crate::stamp::{gather_stamps, StampCollection, StampSpace, ST, ViewStamp, Wrapping};
-use baustein::indices::Index;
+use baustein::indices::{usize_to_i32_arr, Index, VoxelUnits};
 use baustein::re::ConstShape;
 use baustein::traits::Space;
-use baustein::world::FlatPaddedGridCuboid;
+use baustein::world::{FlatPaddedCuboid, OutOfBounds};
 use float_ord::FloatOrd;
 use rand::SeedableRng;
 use rand::distributions::WeightedIndex;
+use std::time::Instant;
 
 
 use crate::extent::Stamped;
+use baustein::traits::Cuboid;
 use rand::distributions::Distribution;
 
 /// This should be enough for all relevant voxel types: 256.
@@ -46,7 +48,11 @@ impl<const D: u8> Superposition<D> {
     const FREE: Self = Self(0);
     /// Everything excluded; use as a mask.
     fn impossible() -> Self {
-        Self((1 << (D as u64)) - 1)
+        match D {
+            // Shifting by the full width overflows.
+            64 => Self(u64::MAX),
+            d => Self((1 << (d as u64)) - 1),
+        }
     }
     fn only(v: VoxelId) -> Self {
         Self::impossible().add(v)
@@ -109,10 +115,8 @@ fn log2(v: usize) -> usize {
     (usize::BITS - v.leading_zeros() - 1) as usize
 }
 
-/// Superposition type
-type FPC<S, const C: u8> = FlatPaddedGridCuboid<Superposition<C>, S>;
 /// Superposition view template
-type SV<'a, StampShape, Shape, const C: u8> = ViewStamp<'a, StampShape, FlatPaddedGridCuboid<Superposition<C>, Shape>>;
+type SV<'a, StampShape, W> = ViewStamp<'a, StampShape, W>;
 
 
 pub fn get_distribution<'a, 's, 't: 'a, WS, TShape, StampShape, const D: u8> (
@@ -159,14 +163,14 @@ pub enum PseudoEntropy {
 /// is lower entropy than the one which can accommodate all 3.
 ///
 /// PE(1/3, 1/3, 1/3) > PE(1/3, 1/3).
-pub fn get_superposition_pseudo_entropy<'s, 't, SShape, TShape, StampShape, const C: u8> (
-    superposition: &SV<'s, StampShape, SShape, C>,
+pub fn get_superposition_pseudo_entropy<'s, 't, W, TShape, StampShape, const C: u8> (
+    superposition: &SV<'s, StampShape, W>,
     stamps: &[(ST<'t, StampShape, TShape>, usize)],
     total: usize,
 ) -> PseudoEntropy
     where
     StampShape: ConstShape,
-    SShape: ConstShape,
+    W: Space<Voxel=Superposition<C>>,
     TShape: ConstShape,
 {
     let possibilities_count = get_distribution(superposition, stamps).count();
@@ -175,7 +179,6 @@ pub fn get_superposition_pseudo_entropy<'s, 't, SShape, TShape, StampShape, cons
     } else if possibilities_count == 1 {
         PseudoEntropy::Collapsed
     } else {
-        println!("possibilities {:?} {}", superposition.offset, possibilities_count);
         PseudoEntropy::Open(get_pseudo_entropy(
             get_distribution(superposition, stamps)
                 .map(|(_stamp, occurrences)| occurrences),
@@ -184,27 +187,46 @@ pub fn get_superposition_pseudo_entropy<'s, 't, SShape, TShape, StampShape, cons
     }
 }
 
-/// Returns the index of the template that has the lowest entropy
-/// in relation to possible stamp choices,
-/// or None if all are either undefined or 0.
-pub fn find_lowest_pseudo_entropy<'a, Shape, SourceShape, StampShape, const C: u8>(
-    wave: &FPC<Shape, C>,
+/// Calculates the pseudo-entropy of every stamp position in the wave.
+pub fn get_pseudo_entropies<'a, W, SourceShape, StampShape, const C: u8>(
+    wave: &W,
     stamps: &[(ST<'a, StampShape, SourceShape>, usize)],
     total: usize,
-) -> Option<Index>
+) -> Vec<(Index, PseudoEntropy)>
     where
-    Shape: ConstShape,
+    W: Space<Voxel=Superposition<C>> + Stamped,
     SourceShape: ConstShape,
     StampShape: ConstShape,
 {
     wave
         .get_stamps_extent::<StampShape>()
         .iter()
-        .map(|i| SV::<StampShape, Shape, C>::new(wave, i))
+        .map(|i| SV::<StampShape, W>::new(wave, i))
         .map(|template| (
             template.offset,
             get_superposition_pseudo_entropy(&template, stamps, total)
         ))
+        .collect()
+}
+
+/// Returns the index of the template that has the lowest entropy
+/// in relation to possible stamp choices,
+/// or None if all are either undefined or 0.
+pub fn find_lowest_pseudo_entropy<'a, W, SourceShape, StampShape, const C: u8>(
+    wave: &W,
+    stamps: &[(ST<'a, StampShape, SourceShape>, usize)],
+    total: usize,
+) -> Option<Index>
+    where
+    W: Space<Voxel=Superposition<C>> + Stamped,
+    SourceShape: ConstShape,
+    StampShape: ConstShape,
+{
+    find_lowest_open(get_pseudo_entropies::<_, _, StampShape, C>(wave, stamps, total))
+}
+
+fn find_lowest_open(entropies: Vec<(Index, PseudoEntropy)>) -> Option<Index> {
+    entropies.into_iter()
         .filter_map(|(index, entropy)| match entropy {
             PseudoEntropy::Open(value) => Some((index, value)),
             _ => None,
@@ -213,6 +235,106 @@ pub fn find_lowest_pseudo_entropy<'a, Shape, SourceShape, StampShape, const C: u
         .map(|(index, _entropy)| index)
 }
 
+/// Positions of all stamps which overlap `index`.
+pub fn get_stamp_positions_containing<StampShape, W, const C: u8>(
+    wave: &W,
+    index: Index,
+) -> Vec<Index>
+    where
+    StampShape: ConstShape,
+    W: Space<Voxel=Superposition<C>> + Stamped,
+{
+    wave.get_stamps_containing::<StampShape>(index).iter().collect()
+}
+
+/// Positions in `stamps` of the stamps still fitting in the view.
+pub fn get_allowed_stamp_ids<'t, WS, TShape, StampShape, const D: u8> (
+    wave_view: &ViewStamp<StampShape, WS>,
+    stamps: &[(ST<'t, StampShape, TShape>, usize)],
+) -> Vec<usize>
+    where
+    StampShape: ConstShape,
+    TShape: ConstShape,
+    WS: Space<Voxel=Superposition<D>>,
+{
+    stamps.iter()
+        .enumerate()
+        .filter(|(_id, (stamp, _occurrences))| wave_view.allows(stamp))
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Occurrences of each voxel value at a single position,
+/// summed over all stamps which can still be placed over it.
+/// Indexed by `VoxelId`.
+/// Like `Superposition`, can only distinguish up to 64 items.
+#[derive(Clone, Copy)]
+pub struct Weights([usize; 64]);
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self([0; 64])
+    }
+}
+
+impl Weights {
+    pub fn get(&self, id: VoxelId) -> usize {
+        self.0[id as usize]
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.0.iter().sum()
+    }
+
+    /// The likelihood of the voxel ending up as `id`.
+    /// None if no stamp fits here any more.
+    pub fn get_probability(&self, id: VoxelId) -> Option<f32> {
+        match self.get_total() {
+            0 => None,
+            total => Some(self.get(id) as f32 / total as f32),
+        }
+    }
+
+    fn add(&mut self, id: VoxelId, occurrences: usize) {
+        self.0[id as usize] += occurrences;
+    }
+}
+
+/// Calculates how likely each voxel value is across the wave,
+/// based on the distribution of stamps which can still be placed.
+///
+/// Every allowed stamp at every stamp position votes for its voxels
+/// with its occurrence count.
+/// Positions not covered by any allowed stamp end up without any weight.
+pub fn get_weights<'a, W, SourceShape, StampShape, const C: u8>(
+    wave: &W,
+    stamps: &[(ST<'a, StampShape, SourceShape>, usize)],
+) -> FlatPaddedCuboid<Weights>
+    where
+    W: Space<Voxel=Superposition<C>> + Stamped + Cuboid,
+    SourceShape: ConstShape,
+    StampShape: ConstShape,
+{
+    let mut weights = FlatPaddedCuboid::<Weights>::new(
+        wave.get_offset(),
+        wave.get_dimensions(),
+    );
+    for offset in wave.get_stamps_extent::<StampShape>().iter() {
+        let template = SV::<StampShape, W>::new(wave, offset);
+        for (stamp, occurrences) in get_distribution(&template, stamps) {
+            let r: Result<(), OutOfBounds> = stamp.visit_indices(|stamp_index| {
+                let index = offset + VoxelUnits(usize_to_i32_arr(stamp_index.0).into());
+                let mut w = weights.get(index);
+                w.add(stamp.get(stamp_index), occurrences);
+                weights.set(index, w)
+            });
+            // Stamp positions never stick out of the wave.
+            r.unwrap();
+        }
+    }
+    weights
+}
+
 /// Always chooses the allowed stamp with the most occurrences.
 pub fn find_preferred_stamp<'a, StampShape, SourceShape, WS, const D: u8>(
     wave_view: ViewStamp<StampShape, WS>,
@@ -243,16 +365,16 @@ pub fn choose_stamp_weighted<'a, StampShape, SourceShape, WS, R, const D: u8>(
     WS: Space<Voxel=Superposition<D>>,
     R: SeedableRng + rand::RngCore, 
 {
-    let weights: Vec<_>
+    let (allowed, weights): (Vec<_>, Vec<_>)
         = get_distribution(&wave_view, stamps.get_distribution())
-            .map(|(stamp_, occurrences)| occurrences)
-            .collect();
+            .unzip();
     let index = WeightedIndex::new(&weights).unwrap();
     
-    &stamps.get_distribution()[index.sample(rng)].0
+    allowed[index.sample(rng)]
 }
 
-pub type SuperpositionSpace<Shape, const D: u8> = FlatPaddedGridCuboid<Superposition<D>, Shape>;
+/// A superposition space with dimensions chosen at runtime.
+pub type SuperpositionSpace<const D: u8> = FlatPaddedCuboid<Superposition<D>>;
 
 /// Example of actual usage.
 ///
@@ -260,35 +382,125 @@ pub type SuperpositionSpace<Shape, const D: u8> = FlatPaddedGridCuboid<Superposi
 /// `wrapping` is unused.
 /// `seed` is the starting superposition space. It should contain something in there, to aid the initial collapse.
 ///
-/// The collapse point selection algorithm is super naive, so no seed is rather discouraged.
-pub fn execute<SourceShape, OutcomeShape, StampShape, const D: u8> (
+/// Always chooses the allowed stamp with the most occurrences, like `find_preferred_stamp`.
+/// Stops at the first contradiction.
+pub fn execute<SourceShape, W, StampShape, const D: u8> (
     template: &StampSpace<SourceShape>,
     wrapping: Wrapping,
-    mut seed: SuperpositionSpace<OutcomeShape, D>,
-) -> SuperpositionSpace<OutcomeShape, D>
+    seed: W,
+) -> W
     where
     SourceShape: ConstShape,
-    OutcomeShape: ConstShape,
+    W: wave::Storage<D>,
     StampShape: ConstShape,
 {
     let stamps = StampCollection::<StampShape, _>::from_iter(gather_stamps(template, wrapping));
-    let mut wave = wave::Naive::new_collapse(seed, &stamps);
+    let (space, _outcome) = execute_with(
+        &stamps,
+        seed,
+        |allowed| {
+            allowed.iter()
+                .enumerate()
+                .max_by_key(|(_i, (_stamp, occurrences))| *occurrences)
+                .map(|(i, _)| i)
+                .unwrap() // Open positions always allow some stamps.
+        },
+        None,
+    );
+    space
+}
+
+/// How a generation ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// Every stamp position has exactly one choice left.
+    Collapsed,
+    /// Some stamp position has no choices left.
+    Contradiction,
+    /// Didn't finish before the deadline.
+    OutOfTime,
+}
+
+/// Collapses the wave fully, choosing stamps the same way as `choose_stamp_weighted`.
+/// Stops as soon as a contradiction is found, or when `deadline` passes.
+pub fn execute_weighted<SourceShape, W, StampShape, R, const D: u8> (
+    stamps: &StampCollection<StampShape, SourceShape>,
+    seed: W,
+    rng: &mut R,
+    deadline: Instant,
+) -> (W, Outcome)
+    where
+    SourceShape: ConstShape,
+    W: wave::Storage<D>,
+    StampShape: ConstShape,
+    R: SeedableRng + rand::RngCore,
+{
+    execute_with(
+        stamps,
+        seed,
+        |allowed| {
+            WeightedIndex::new(allowed.iter().map(|(_stamp, occurrences)| *occurrences))
+                .unwrap()
+                .sample(rng)
+        },
+        Some(deadline),
+    )
+}
+
+/// The generation loop shared by `execute` and `execute_weighted`.
+///
+/// Repeatedly picks the stamp position with the lowest pseudo-entropy,
+/// and places a stamp there.
+/// `choose` receives the stamps allowed at the position along with their occurrences,
+/// and returns the position of the chosen stamp within them.
+///
+/// Stops at the first contradiction, or when `deadline` passes, if there is one.
+pub fn execute_with<'a, SourceShape, W, StampShape, F, const D: u8> (
+    stamps: &'a StampCollection<'a, StampShape, SourceShape>,
+    seed: W,
+    mut choose: F,
+    deadline: Option<Instant>,
+) -> (W, Outcome)
+    where
+    SourceShape: ConstShape,
+    W: wave::Storage<D>,
+    StampShape: ConstShape,
+    F: FnMut(&[(&'a ST<'a, StampShape, SourceShape>, usize)]) -> usize,
+{
+    let mut wave = wave::Naive::new_collapse(seed, stamps);
     loop {
-        let candidate = find_lowest_pseudo_entropy(
+        if let Some(deadline) = deadline {
+            if Instant::now() > deadline {
+                return (wave.into_space(), Outcome::OutOfTime);
+            }
+        }
+        // Make sure all new collapses are resolved before looking for entropy.
+        if wave.collapse(&wave.get_extent(), stamps) {
+            continue;
+        }
+        let entropies = get_pseudo_entropies::<_, _, StampShape, D>(
             wave.get_world(),
             stamps.get_distribution(),
             stamps.get_total_occurrences(),
         );
-        if let Some(index) = candidate {
-            let stamp = find_preferred_stamp(
-                ViewStamp::new(&wave.get_world(), index),
-                &stamps,
-            );
-            // Trigger collapse
-            wave.limit_stamp(index, &stamp, &stamps).unwrap();
-        } else {
-            // Nothing to collapse any more.
-            return wave.into_space();
+        let contradicted = entropies.iter()
+            .any(|(_index, entropy)| match entropy {
+                PseudoEntropy::Impossible => true,
+                _ => false,
+            });
+        if contradicted {
+            return (wave.into_space(), Outcome::Contradiction);
+        }
+        match find_lowest_open(entropies) {
+            Some(index) => {
+                let allowed: Vec<_> = {
+                    let view = ViewStamp::new(wave.get_world(), index);
+                    get_distribution(&view, stamps.get_distribution()).collect()
+                };
+                let stamp = allowed[choose(&allowed)].0;
+                wave.limit_stamp(index, stamp, stamps).unwrap();
+            },
+            None => return (wave.into_space(), Outcome::Collapsed),
         }
     }
 }
@@ -298,6 +510,8 @@ mod test {
     use super::*;
     use assert_float_eq::*;
     use baustein::re::ConstAnyShape;
+    use baustein::traits::IterableSpace;
+    use baustein::world::FlatPaddedGridCuboid;
     use more_asserts::*;
 
     #[test]
@@ -307,6 +521,15 @@ mod test {
         }
     }
 
+    #[test]
+    fn superposition_full_width() {
+        let s = Superposition::<64>::only(63);
+        assert!(s.allows(63));
+        assert!(!s.allows(0));
+        assert_eq!(s.count_allowed(), 1);
+        assert_eq!(Superposition::<64>::FREE.count_allowed(), 64);
+    }
+
     #[test]
     #[should_panic]
     fn log0() {
@@ -491,4 +714,83 @@ mod test {
         let lowest = find_lowest_pseudo_entropy(&world, &stamps, total);
         assert_eq!(lowest, Some([0, 1, 0].into()));
     }
+
+    #[test]
+    fn weights_follow_constraints() {
+        type Shape = ConstAnyShape<4, 4, 4>;
+        type StampShape = ConstAnyShape<1, 2, 1>;
+
+        let extent = FlatPaddedGridCuboid::<(), Shape>::new([0, 0, 0].into());
+        // Split into 2 areas
+        let world = extent.map_index(|i, _| {
+            if i.y() < 2 { 1 }
+            else { 0 }
+        });
+        let world: FlatPaddedGridCuboid<u8, Shape> = world.into();
+        let stamps: Vec<_>
+            = gather_stamps::<_, StampShape>(&world, Wrapping)
+            .into_iter()
+            .collect();
+
+        let extent = FlatPaddedGridCuboid::<(), Shape>::new([0, 0, 0].into());
+        let world = extent.map_index(|i, _| {
+            if i == [0,0,0].into() { Superposition::only(1) }
+            else { Superposition::FREE }
+        });
+        let world: FlatPaddedGridCuboid<Superposition<2>, Shape> = world.into();
+
+        let weights = get_weights::<_, _, StampShape, 2>(&world, &stamps);
+        let collapsed = weights.get([0, 0, 0].into());
+        assert_eq!(collapsed.get_probability(0), Some(0.0));
+        assert_eq!(collapsed.get_probability(1), Some(1.0));
+        let free = weights.get([2, 2, 2].into());
+        assert_gt!(free.get_probability(0).unwrap(), 0.0);
+        assert_gt!(free.get_probability(1).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn execute_collapses() {
+        type Shape = ConstAnyShape<4, 4, 4>;
+        type StampShape = ConstAnyShape<1, 2, 1>;
+
+        let extent = FlatPaddedGridCuboid::<(), Shape>::new([0, 0, 0].into());
+        let world = extent.map_index(|i, _| {
+            if i.y() < 2 { 1 }
+            else { 0 }
+        });
+        let world: FlatPaddedGridCuboid<u8, Shape> = world.into();
+
+        let seed = FlatPaddedGridCuboid::<Superposition<2>, Shape>::new([0, 0, 0].into());
+        let space = execute::<_, _, StampShape, 2>(&world, Wrapping, seed);
+        let mut open = 0;
+        space.visit_indices(|i| {
+            if space.get(i).count_allowed() != 1 {
+                open += 1;
+            }
+        });
+        assert_eq!(open, 0);
+    }
+
+    #[test]
+    fn execute_weighted_collapses() {
+        type Shape = ConstAnyShape<4, 4, 4>;
+        type StampShape = ConstAnyShape<1, 2, 1>;
+
+        let extent = FlatPaddedGridCuboid::<(), Shape>::new([0, 0, 0].into());
+        // Split into 2 areas
+        let world = extent.map_index(|i, _| {
+            if i.y() < 2 { 1 }
+            else { 0 }
+        });
+        let world: FlatPaddedGridCuboid<u8, Shape> = world.into();
+        let stamps = StampCollection::<StampShape, _>::from_iter(
+            gather_stamps(&world, Wrapping)
+        );
+
+        let seed = FlatPaddedGridCuboid::<Superposition<2>, Shape>::new([0, 0, 0].into());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let deadline = Instant::now() + std::time::Duration::from_secs(10);
+        let (_space, outcome) = execute_weighted(&stamps, seed, &mut rng, deadline);
+        assert_eq!(outcome, Outcome::Collapsed);
+    }
 }
@@ -160,7 +160,8 @@ impl<'a, V, Shape, S> ViewStamp<'a, Shape, S>
         self.space.get(self.offset + VoxelUnits(usize_to_i32_arr(index.0).into()))
     }
 
-    fn get_samples(&self) -> Vec<V> {
+    /// All voxels, in the order of `Shape` linear indices.
+    pub fn get_samples(&self) -> Vec<V> {
         let mut out = Vec::with_capacity(Shape::SIZE);
         let r: Result<(), ()> = self.visit_indices(|i| Ok(out.push(self.get(i))));
         r.unwrap();
@@ -10,30 +10,47 @@ use crate::stamp::{CollapseOutcomes, StampCollection, ViewStamp};
 
 use baustein::indices::{usize_to_i32_arr, Index, VoxelUnits};
 use baustein::re::ConstShape;
-use baustein::world::{FlatPaddedGridCuboid, OutOfBounds};
+use baustein::world::{FlatPaddedCuboid, FlatPaddedGridCuboid, OutOfBounds};
 
 // Used traits
 use crate::extent::Stamped;
 use baustein::traits::Space;
 use baustein::traits::Cuboid;
 
+/// A cuboid of superpositions which waves keep their state in.
+pub trait Storage<const C: u8>: Space<Voxel=Superposition<C>> + Cuboid + Stamped {
+    fn set(&mut self, index: Index, value: Superposition<C>) -> Result<(), OutOfBounds>;
+}
+
+impl<S: ConstShape, const C: u8> Storage<C> for FlatPaddedGridCuboid<Superposition<C>, S> {
+    fn set(&mut self, index: Index, value: Superposition<C>) -> Result<(), OutOfBounds> {
+        FlatPaddedGridCuboid::set(self, index, value)
+    }
+}
+
+/// Dimensions chosen at runtime.
+impl<const C: u8> Storage<C> for FlatPaddedCuboid<Superposition<C>> {
+    fn set(&mut self, index: Index, value: Superposition<C>) -> Result<(), OutOfBounds> {
+        FlatPaddedCuboid::set(self, index, value)
+    }
+}
+
 /// Doesn't do anything special for you. Just a dumb container.
 /// Like all waves, it handles propagating collapses.
-pub struct Naive<S: ConstShape, const C: u8> {
-    world: FlatPaddedGridCuboid<Superposition<C>, S>,
+#[derive(Clone)]
+pub struct Naive<W: Storage<C>, const C: u8> {
+    world: W,
 }
 
-impl<S: ConstShape, const C: u8> Naive<S, C> {
+impl<W: Storage<C>, const C: u8> Naive<W, C> {
     // Not sure which constructor is a better idea.
     // It probably makes sense to allow edition without collapsing.
-    pub fn new(
-        world: FlatPaddedGridCuboid<Superposition<C>, S>,
-    ) -> Self {
+    pub fn new(world: W) -> Self {
         Self { world }
     }
 
     pub fn new_collapse<StampShape: ConstShape, SourceShape: ConstShape>(
-        world: FlatPaddedGridCuboid<Superposition<C>, S>,
+        world: W,
         stamps: &StampCollection<StampShape, SourceShape>,
     ) -> Self {
         let mut new = Self { world };
@@ -53,7 +70,7 @@ impl<S: ConstShape, const C: u8> Naive<S, C> {
         Extent::new(self.get_offset(), self.get_beyond_opposite_corner())
     }
 
-    pub fn get_world(&self) -> &FlatPaddedGridCuboid<Superposition<C>, S> {
+    pub fn get_world(&self) -> &W {
         &self.world
     }
 
@@ -63,7 +80,7 @@ impl<S: ConstShape, const C: u8> Naive<S, C> {
 
     /// This can either lock or unlock possibilities.
     /// This is intentional to allow interactivity.
-    fn set<StampShape: ConstShape, SourceShape: ConstShape>(
+    pub fn set<StampShape: ConstShape, SourceShape: ConstShape>(
         &mut self,
         index: Index,
         value: Superposition<C>,
@@ -148,7 +165,7 @@ impl<S: ConstShape, const C: u8> Naive<S, C> {
         ret
     }
 
-    pub fn into_space(self) -> FlatPaddedGridCuboid<Superposition<C>, S> {
+    pub fn into_space(self) -> W {
         self.world
     }
 }
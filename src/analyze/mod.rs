@@ -3,34 +3,46 @@
  */
 pub mod render;
 
+use baustein::prefab::PaletteVoxel;
 use baustein::re::ConstPow2Shape;
-use baustein::world::FlatPaddedGridCuboid;
-use bevy::ecs::system::Commands;
+use baustein::world::{FlatPaddedCuboid, FlatPaddedGridCuboid};
+use bevy::ecs::system::{ Commands, Res };
 use bevy::transform::components::Transform;
 
 use render::{ Analyzed, StressChunk };
+use crate::Palette;
 use crate::stress::{ get_initial_forces, distribute, get_stress_sum, process_newton_discrepancy, Force, StressVoxel };
 
 // Used traits
 use baustein::traits::Space;
 
 
-fn test_bound2b() -> Analyzed {
+fn test_bound2b(palette: &Palette) -> Analyzed {
     // 4x4x4
     type Shape = ConstPow2Shape<4, 4, 4>;
-    let mut world = FlatPaddedGridCuboid::<StressVoxel, Shape>::new([0, 0, 0].into());
-    world.set([1, 1, 1].into(), StressVoxel::Bedrock).unwrap();
-    world.set([1, 1, 2].into(), StressVoxel::Bound).unwrap();
-    world.set([1, 1, 3].into(), StressVoxel::Bound).unwrap();
-    world.set([1, 1, 4].into(), StressVoxel::Bound).unwrap();
-    world.set([1, 1, 5].into(), StressVoxel::Bound).unwrap();
+    // A column of building material standing on the ground, as the editor would have it.
+    let ground = palette.find("Grass")
+        .expect("The stress demo needs \"Grass\" in the palette");
+    let material = palette.find("Wood")
+        .expect("The stress demo needs \"Wood\" in the palette");
+    let mut editor_world = FlatPaddedGridCuboid::<PaletteVoxel, Shape>::new([0, 0, 0].into());
+    editor_world.set([1, 1, 1].into(), ground).unwrap();
+    editor_world.set([1, 1, 2].into(), material).unwrap();
+    editor_world.set([1, 1, 3].into(), material).unwrap();
+    editor_world.set([1, 1, 4].into(), material).unwrap();
+    editor_world.set([1, 1, 5].into(), material).unwrap();
+
+    let world: FlatPaddedCuboid<StressVoxel> = editor_world
+        .map(|v| StressVoxel::from_palette(palette, v))
+        .into();
 
     // For this algorithm, empty is ignored, and bedrock forces should too.
     let weights = world.map(|v| Force(1.0));
     let mut outforces = get_initial_forces(&world);
 
     for i in 0..30 {
-        outforces = distribute(&world, &weights, &outforces);
+        outforces = distribute(&world, &weights, &outforces)
+            .expect("Weights and forces are mapped from the world");
         let balance = process_newton_discrepancy(&outforces);
 
     }
@@ -61,8 +73,9 @@ fn test_bound2b() -> Analyzed {
 
 pub fn spawn_test_chunk_2b(
     mut commands: Commands,
+    palette: Res<Palette>,
 ) {
     commands.spawn()
-        .insert(test_bound2b())
+        .insert(test_bound2b(&palette))
         .insert(Transform::from_xyz(5.0, 5.0, 5.0));
 }
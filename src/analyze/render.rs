@@ -10,6 +10,7 @@ use baustein::world::{ FlatPaddedGridCuboid, View };
 
 use bevy::app;
 use bevy::prelude::*;
+use bevy::render::camera::RenderLayers;
 use block_mesh;
 use block_mesh::{visible_block_faces, UnitQuadBuffer, MergeVoxel, RIGHT_HANDED_Y_UP_CONFIG, UnorientedUnitQuad};
 use feldspar::bb::mesh::PosNormMesh;
@@ -95,7 +96,7 @@ pub struct StressMesh;
 pub fn update_meshes(
     mut commands: Commands,
     mesh_material: Res<MeshMaterial>,
-    spaces: Query<(&Analyzed, &Transform)>,
+    spaces: Query<(&Analyzed, &Transform, Option<&RenderLayers>)>,
     mut meshes: ResMut<Assets<Mesh>>,
     chunk_meshes: Query<Entity, With<StressMesh>>,
 ) {
@@ -105,7 +106,7 @@ pub fn update_meshes(
     }
     // And create the occupied ones again.
     // Wasteful, I know. I'm testing!
-    for (space, transform) in spaces.iter() {
+    for (space, transform, layers) in spaces.iter() {
         let quads = generate_buffer_fast(&space.0);
         let material_lookup = |quad: &UnorientedUnitQuad| {
             let material = to_material(space.0.get(to_i32_arr(quad.minimum).into()));
@@ -113,7 +114,7 @@ pub fn update_meshes(
         };
         let mesh = mesh_from_quads(quads, &space.0, material_lookup);
         if let Some((mesh, materials)) = mesh {
-            commands
+            let mut entity = commands
                 .spawn_bundle(
                     create_voxel_mesh_bundle(
                         mesh,
@@ -121,11 +122,16 @@ pub fn update_meshes(
                         mesh_material.0.clone(),
                         &mut meshes,
                     )
-                )
+                );
+            entity
                 // This won't work within a hierarchy
                 .insert(transform.clone())
                 .insert(StressMesh)
                 ;
+            // Lets the analyzed data show up in other windows.
+            if let Some(layers) = layers {
+                entity.insert(*layers);
+            }
         }
     }
 }
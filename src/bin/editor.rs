@@ -1,8 +1,9 @@
 use bevy::app::prelude::*;
-use domostroj::{Config, EditorPlugin};
+use domostroj::{Config, EditorPlugin, Palette};
+use std::error::Error;
 
 
-fn main() -> Result<(), ron::Error> {
+fn main() -> Result<(), Box<dyn Error>> {
     env_logger::Builder::from_default_env()
         // Filter out some noisy crates
         .filter(Some("gfx_backend_metal"), log::LevelFilter::Error)
@@ -10,9 +11,10 @@ fn main() -> Result<(), ron::Error> {
         .init();
 
     let config = Config::read_file("config.ron")?;
+    let palette = Palette::read_file(&config.palette_path)?;
 
     App::build()
-        .add_plugin(EditorPlugin::new(config))
+        .add_plugin(EditorPlugin::new(config, palette))
         .run();
     Ok(())
 }
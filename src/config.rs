@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use crate::generate::PostprocessPass;
 use smooth_bevy_cameras::controllers::{
     orbit::OrbitCameraController, unreal::UnrealCameraController,
 };
@@ -8,7 +9,21 @@ pub struct Config {
     pub feldspar: feldspar::Config,
     pub bevy: BevyConfig,
     pub database_path: String,
+    /// The file with voxel types.
+    #[serde(default = "default_palette_path")]
+    pub palette_path: String,
     pub camera: CameraConfig,
+    #[serde(default)]
+    pub generator: GeneratorConfig,
+    #[serde(default)]
+    pub scene: SceneConfig,
+    #[serde(default)]
+    pub postprocess: PostprocessConfig,
+}
+
+/// Lets older config files without the palette keep working.
+fn default_palette_path() -> String {
+    "palette.ron".into()
 }
 
 #[derive(Clone, Copy, Deserialize, Default)]
@@ -16,6 +31,88 @@ pub struct BevyConfig {
     pub wireframes: bool,
 }
 
+/// Limits for generating the whole scene in one go.
+/// Missing fields take the defaults.
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    /// Time for all attempts together.
+    pub time_budget_ms: u64,
+    /// How many times to try before giving up on contradictions.
+    pub max_attempts: usize,
+    /// How many past states of the scene to keep for stepping back.
+    pub history_length: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            time_budget_ms: 10000,
+            max_attempts: 10,
+            history_length: 256,
+        }
+    }
+}
+
+/// The starting point of the generator scene.
+/// Missing fields take the defaults.
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+pub struct SceneConfig {
+    /// Size in voxels. The scene is centred on the origin.
+    pub dimensions: [usize; 3],
+    /// Cells constrained before generation starts.
+    pub seed: Vec<SeedCell>,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+pub struct SeedCell {
+    pub position: [i32; 3],
+    /// Names from the palette.
+    pub allowed: Vec<String>,
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        Self {
+            dimensions: [10, 10, 10],
+            seed: vec![
+                SeedCell {
+                    position: [0, 1, 0],
+                    allowed: vec!["Empty".into()],
+                },
+                SeedCell {
+                    position: [0, 0, 0],
+                    allowed: vec!["Grass".into()],
+                },
+            ],
+        }
+    }
+}
+
+/// Cleanup applied to generated scenes.
+/// Missing fields take the defaults.
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+pub struct PostprocessConfig {
+    /// Applied in this order.
+    pub passes: Vec<PostprocessPass>,
+    /// Collapse the surroundings of changed voxels again.
+    pub recollapse: bool,
+}
+
+impl Default for PostprocessConfig {
+    fn default() -> Self {
+        Self {
+            passes: vec![
+                PostprocessPass::RemoveIslands,
+                PostprocessPass::FillCavities { max_volume: 1 },
+            ],
+            recollapse: true,
+        }
+    }
+}
+
 impl Config {
     pub fn read_file(path: &str) -> Result<Self, ron::Error> {
         let reader = std::fs::File::open(path)?;
@@ -35,3 +132,69 @@ impl Default for CameraConfig {
         CameraConfig::Orbit(Default::default())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partial_scene() {
+        let scene: SceneConfig = ron::de::from_str("(dimensions: (20, 8, 20))").unwrap();
+        assert_eq!(scene.dimensions, [20, 8, 20]);
+        assert_eq!(scene.seed, SceneConfig::default().seed);
+    }
+
+    #[test]
+    fn generator_without_history() {
+        let generator: GeneratorConfig = ron::de::from_str(
+            "(time_budget_ms: 5000, max_attempts: 3)"
+        ).unwrap();
+        assert_eq!(generator.time_budget_ms, 5000);
+        assert_eq!(generator.max_attempts, 3);
+        assert_eq!(generator.history_length, GeneratorConfig::default().history_length);
+    }
+
+    #[test]
+    fn partial_postprocess() {
+        let postprocess: PostprocessConfig = ron::de::from_str("(passes: [RemoveIslands])").unwrap();
+        assert_eq!(postprocess.passes, vec![PostprocessPass::RemoveIslands]);
+        assert!(postprocess.recollapse);
+    }
+
+    /// A config from before the generator could be configured.
+    #[test]
+    fn old_config() {
+        let config: Config = ron::de::from_str(r#"(
+            bevy: (
+                wireframes: false,
+            ),
+            feldspar: (
+                map: (
+                    superchunk_exponent: 9,
+                    chunk_exponent: 4,
+                    num_lods: 4,
+                ),
+                render: (
+                    debug_chunk_boundaries: false,
+                ),
+                chunk_cache: (
+                    max_cached_chunks: 100000,
+                    max_chunks_compressed_per_frame_per_thread: 50,
+                ),
+                witness_radius: 1024,
+            ),
+            camera: Unreal(UnrealCameraController(
+                enabled: true,
+                mouse_rotate_sensitivity: (0.002, 0.002),
+                mouse_translate_sensitivity: (0.1, 0.1),
+                trackpad_translate_sensitivity: (0.1, -0.1),
+                smoothing_weight: 0.9,
+            )),
+            database_path: "/tmp/world1",
+        )"#).unwrap();
+        assert_eq!(config.palette_path, "palette.ron");
+        assert_eq!(config.generator, GeneratorConfig::default());
+        assert_eq!(config.scene, SceneConfig::default());
+        assert_eq!(config.postprocess, PostprocessConfig::default());
+    }
+}
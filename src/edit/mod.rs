@@ -8,25 +8,25 @@ use crate::camera;
 use crate::config::{ CameraConfig, Config };
 use crate::EditorState;
  
-use baustein::indices::{to_i32_arr, VoxelUnits};
+use baustein::indices::{to_i32_arr, ChunkIndex, VoxelUnits};
 use baustein::prefab::PaletteVoxel;
-use baustein::re::{ ConstPow2Shape, ConstShape };
-use baustein::render::{ mesh_from_quads, MeshMaterial };
+use baustein::re::{ ConstAnyShape, ConstPow2Shape };
+use baustein::render::{
+    create_voxel_mesh_bundle, despawn_dirty, generate_greedy_buffer_fast, get_chunk_mesh_offset,
+    mesh_from_quads, Looks, MeshMaterial,
+};
 use baustein::traits::Space;
 use baustein::world::FlatPaddedGridCuboid;
 use bevy::app::AppBuilder;
 use bevy::asset::Assets;
 use bevy::ecs::entity::Entity;
-use bevy::ecs::query::With;
 use bevy::ecs::schedule::SystemSet;
-use bevy::ecs::system::{ Commands, Query, Res, ResMut };
+use bevy::ecs::system::{ Commands, Local, Query, Res, ResMut };
 use bevy::math::Vec3;
 use bevy::render::mesh::Mesh;
 use bevy::transform::components::Transform;
 use bincode;
-use block_mesh;
-use block_mesh::{ greedy_quads, GreedyQuadsBuffer, MergeVoxel, UnorientedQuad, RIGHT_HANDED_Y_UP_CONFIG };
-use feldspar::prelude::create_voxel_mesh_bundle;
+use block_mesh::UnorientedQuad;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
@@ -43,13 +43,24 @@ use bevy::prelude::ParallelSystemDescriptorCoercion;
 
 pub type Shape = ConstPow2Shape<5, 5, 5>;
 
+/// A chunk with a layer of neighbouring voxels on each side.
+type ChunkMeshShape = ConstAnyShape<18, 18, 18>;
+
 /// A wrapper over a mundane chunk, for the purpose of becoming the Bevy resource.
 #[derive(Clone)]
 pub struct World(pub FlatPaddedGridCuboid<PaletteVoxel, Shape>);
 
+/// Where all new worlds start.
+const ORIGIN: [i32; 3] = [0, -8, 0];
+
+/// Create a World with nothing in it.
+pub fn empty() -> World {
+    World(FlatPaddedGridCuboid::new(ORIGIN.into()))
+}
+
 /// Create a default World with a grassy, diggable floor below level 0.
 pub fn floor() -> World {
-    let extent = FlatPaddedGridCuboid::<(), Shape>::new([0, -8, 0].into());
+    let extent = FlatPaddedGridCuboid::<(), Shape>::new(ORIGIN.into());
     let world = extent.map_index(|i, _| {
         if i.y() < 0 {
             PaletteVoxel(1) // hopefully grass
@@ -96,100 +107,97 @@ pub fn handle_events(
 fn load(mut world: &mut World, path: PathBuf) -> Result<(), Box<dyn Error>>{
     let f = File::open(path)?;
     let mut f = BufReader::new(f);
-    world.0 = bincode::deserialize_from(&mut f)?;
+    let loaded: FlatPaddedGridCuboid<_, _> = bincode::deserialize_from(&mut f)?;
+    // The meshes of the old world must go, even where the new one doesn't reach.
+    let mut dirty = world.0.take_dirty();
+    dirty.mark_cuboid(world.0.get_offset(), world.0.get_dimensions());
+    world.0 = loaded;
+    world.0.mark_dirty(dirty);
     Ok(())
 }
 
-fn save(world: World, path: PathBuf) -> Result<(), Box<dyn Error>>{
+pub(crate) fn save(world: World, path: PathBuf) -> Result<(), Box<dyn Error>>{
     let f = File::create(path)?;
     let mut f = BufWriter::new(f);
     bincode::serialize_into(&mut f, &world.0)?;
     Ok(())
 }
 
-// Older version needed for block_mesh
-type BlockMeshShape = block_mesh::ndshape::ConstPow2Shape3u32::<5, 5, 5>;
-
-/// Marks which meshes should despawn
-pub struct EditMesh;
+/// Marks which meshes should despawn, and which chunk they show.
+pub struct EditMesh(ChunkIndex);
 
+/// Re-meshes only the chunks which changed since the last frame.
 pub fn update_meshes(
     mut commands: Commands,
     mesh_material: Res<MeshMaterial>,
     mesh_cutoff: Res<slice::MeshCutoff>,
-    space: Res<World>,
+    mut last_cutoff: Local<Option<i32>>,
+    looks: Res<Looks>,
+    mut space: ResMut<World>,
     mut meshes: ResMut<Assets<Mesh>>,
-    edit_meshes: Query<Entity, With<EditMesh>>,
+    edit_meshes: Query<(Entity, &EditMesh)>,
 ) {
-    // Get rid of all meshes
-    for cm in edit_meshes.iter() {
-        commands.entity(cm).despawn()
+    let mut dirty = space.0.take_dirty();
+    let offset = space.0.get_offset();
+    let dimensions = space.0.get_dimensions();
+    if looks.is_changed() {
+        dirty.mark_cuboid(offset, dimensions);
+    }
+    // Only the layers between the old and the new cutoff change.
+    let mesh_cutoff = mesh_cutoff.0;
+    match *last_cutoff {
+        Some(last) if last == mesh_cutoff => {},
+        Some(last) => {
+            let bottom = last.min(mesh_cutoff).max(offset.y());
+            let top = last.max(mesh_cutoff).min(offset.y() + dimensions[1] as i32);
+            if bottom < top {
+                dirty.mark_cuboid(
+                    [offset.x(), bottom, offset.z()].into(),
+                    [dimensions[0], (top - bottom) as usize, dimensions[2]],
+                );
+            }
+        },
+        None => dirty.mark_cuboid(offset, dimensions),
     }
-    // And create the occupied ones again.
-    // Wasteful, I know. I'm testing!
+    *last_cutoff = Some(mesh_cutoff);
+
+    if dirty.is_empty() {
+        return;
+    }
+    despawn_dirty(&mut commands, &dirty, &edit_meshes, |mesh| mesh.0);
 
-    // The creation of this space can be optimized:
-    // create an extent that doesn't go above the offset.
-    // This is annoying, so it's skipped.
-    let mesh_cutoff = mesh_cutoff.0 as i32;
     let space = space.0.map_index(|i, v| {
         if i.y() < mesh_cutoff { v }
         else { Default::default() }
     });
-    let space = FlatPaddedGridCuboid::<_, Shape>::new_from_space(&space, space.get_offset());
-    
-    let quads = generate_greedy_buffer_fast(&space);
-    let material_lookup = |quad: &UnorientedQuad| {
-        let i = space.get(space.get_offset() + VoxelUnits(to_i32_arr(quad.minimum))).0;
-        let mut material = [0; 4];
-        let i = i - 1; // 0 is empty
-        material[i as usize] = 1;
-        [material, material, material, material]
-    };
-    let mesh = mesh_from_quads(quads, &space, material_lookup);
-    if let Some((mesh, materials)) = mesh {
-        commands
-            .spawn_bundle(
-                create_voxel_mesh_bundle(
-                    mesh,
-                    materials,
-                    mesh_material.0.clone(),
-                    &mut meshes,
+    for index in dirty.iter() {
+        let chunk = FlatPaddedGridCuboid::<_, ChunkMeshShape>::new_from_space(
+            &space,
+            get_chunk_mesh_offset(index),
+        );
+        let quads = generate_greedy_buffer_fast(&chunk);
+        let look_lookup = |quad: &UnorientedQuad| {
+            let look = looks.get(chunk.get(chunk.get_offset() + VoxelUnits(to_i32_arr(quad.minimum))));
+            [look, look, look, look]
+        };
+        let mesh = mesh_from_quads(quads, &chunk, look_lookup);
+        if let Some((mesh, looks)) = mesh {
+            commands
+                .spawn_bundle(
+                    create_voxel_mesh_bundle(
+                        mesh,
+                        looks,
+                        &mesh_material,
+                        &mut meshes,
+                    )
                 )
-            )
-            .insert(Transform::from_translation(space.get_offset().into()))
-            .insert(EditMesh)
-            ;
+                .insert(Transform::from_translation(chunk.get_offset().into()))
+                .insert(EditMesh(index))
+                ;
+        }
     }
 }
 
-/// This needs to stay here because each BlockmeshShape is specific to each renderer.
-fn generate_greedy_buffer_fast<V, Shape>(
-    view: &FlatPaddedGridCuboid<V, Shape>,
-) -> GreedyQuadsBuffer
-    where
-    V: MergeVoxel + Copy + Default,
-    Shape: ConstShape,
-{
-    let samples = view.get_samples();
-    let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-    let mut buffer = GreedyQuadsBuffer::new(samples.len());
-
-    greedy_quads(
-        samples,
-        &BlockMeshShape {},
-        [0, 0, 0],
-        [
-            <Shape as ConstShape>::ARRAY[0] as u32 - 1,
-            <Shape as ConstShape>::ARRAY[1] as u32 - 1,
-            <Shape as ConstShape>::ARRAY[2] as u32 - 1,
-        ],
-        &faces,
-        &mut buffer,
-    );
-    buffer
-}
-
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum CurrentTool {
     DragFace,//(DragFaceState),
@@ -3,7 +3,6 @@
  */
 
 use baustein::indices::Index;
-use baustein::prefab::PaletteVoxel;
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContext, EguiPlugin, EguiSettings};
 use rfd;
@@ -12,7 +11,7 @@ use std::sync::Mutex;
 use std::sync::mpsc::Sender;
 use std::thread;
 
-use crate::CursorRay;
+use crate::{CursorRay, Palette};
 use crate::edit;
 use crate::edit::CurrentTool;
 use crate::edit::slice;
@@ -32,11 +31,12 @@ pub fn process(
     mut egui_ctx: ResMut<EguiContext>,
     mut tool: ResMut<CurrentTool>,
     mut slice_state: ResMut<slice::State>,
+    palette: Res<Palette>,
     events: Res<Mutex<Sender<edit::Event>>>,
 ) {
     let old_state = State { tool: *tool, slice_state: *slice_state };
     let events = events.lock().unwrap();
-    let new_state = process_panel(&mut *egui_ctx, old_state, &*voxel_info, &events);
+    let new_state = process_panel(&mut *egui_ctx, old_state, &*voxel_info, &*palette, &events);
     if new_state != old_state {
         *tool = new_state.tool;
         *slice_state = new_state.slice_state;
@@ -68,6 +68,7 @@ fn process_panel(
     egui_ctx: &mut EguiContext,
     mut ui_state: State,
     voxel_info: &Option<VoxelInfo>,
+    palette: &Palette,
     mut events: &Sender<edit::Event>,
 ) -> State {
     egui::SidePanel::left("side_panel")
@@ -89,14 +90,12 @@ fn process_panel(
                         .text("level")
                 );
 
-                ui.horizontal(|ui| {
+                ui.horizontal_wrapped(|ui| {
                     let voxel = &mut ui_state.slice_state.voxel_type;
                     // Those *really* need pictures.
-                    ui.radio_value(voxel, PaletteVoxel(0), "Empty");
-                    ui.radio_value(voxel, PaletteVoxel(1), "Grass");
-                    ui.radio_value(voxel, PaletteVoxel(2), "Ground");
-                    ui.radio_value(voxel, PaletteVoxel(3), "Ice");
-                    ui.radio_value(voxel, PaletteVoxel(4), "?");
+                    for (palette_voxel, entry) in palette.iter() {
+                        ui.radio_value(voxel, palette_voxel, &entry.name);
+                    }
                 });
             }
 
@@ -104,7 +103,7 @@ fn process_panel(
                 ui.heading("Voxel");
     
                 ui.label(format!("{:?}", index));
-                ui.label(format!("{:?}", contents));
+                ui.label(format!("{:?} ({})", contents, palette.get_name(*contents)));
             }
             
             ui.heading("Scene");
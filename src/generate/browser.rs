@@ -0,0 +1,178 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Lists the stamps from the source, and lets the user tune them.
+ *
+ * Settings are kept by the contents of the stamp,
+ * so they survive reloading the stamps from a modified source.
+ */
+
+use bevy_egui::egui;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use wfc_3d as wfc;
+use wfc::VoxelId;
+use wfc::stamp::{gather_stamps, StampSpace, Wrapping};
+
+use crate::{edit, Palette};
+use crate::generate;
+use crate::generate::{collapse, inspect};
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Setting {
+    pub enabled: bool,
+    /// Multiplies the occurrences of the stamp during selection.
+    pub weight: f32,
+}
+
+impl Default for Setting {
+    fn default() -> Self {
+        Self { enabled: true, weight: 1.0 }
+    }
+}
+
+impl Setting {
+    fn weigh(&self, occurrences: usize) -> Option<usize> {
+        if self.enabled {
+            // A stamp that's enabled should never disappear by rounding.
+            Some(cmp::max(1, (occurrences as f32 * self.weight).round() as usize))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Entry {
+    pub samples: Vec<VoxelId>,
+    pub occurrences: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Order {
+    Common,
+    Rare,
+}
+
+/// The resource holding the stamp list.
+pub struct Browser {
+    pub open: bool,
+    pub order: Order,
+    /// Settings differ from what the generator uses.
+    pub modified: bool,
+    source: Option<StampSpace<edit::Shape>>,
+    entries: Vec<Entry>,
+    settings: HashMap<Vec<VoxelId>, Setting>,
+}
+
+impl Browser {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            order: Order::Common,
+            modified: false,
+            source: None,
+            entries: Vec::new(),
+            settings: HashMap::new(),
+        }
+    }
+
+    /// Lists the stamps found in the source and builds them with current settings.
+    pub fn load(&mut self, source: StampSpace<edit::Shape>) -> collapse::Stamps {
+        self.entries = gather_stamps::<_, collapse::StampShape>(&source, Wrapping)
+            .into_iter()
+            .map(|(stamp, occurrences)| Entry {
+                samples: stamp.get_samples(),
+                occurrences,
+            })
+            .collect();
+        self.source = Some(source);
+        self.sort();
+        self.build().unwrap()
+    }
+
+    /// Builds stamps from the last loaded source, applying settings.
+    pub fn build(&mut self) -> Option<collapse::Stamps> {
+        let source = self.source.clone()?;
+        self.modified = false;
+        let settings = &self.settings;
+        Some(collapse::Stamps::from_source_weighted(
+            source,
+            |samples, occurrences| {
+                settings.get(samples)
+                    .copied()
+                    .unwrap_or_default()
+                    .weigh(occurrences)
+            },
+        ))
+    }
+
+    fn sort(&mut self) {
+        let order = self.order;
+        self.entries.sort_by(|a, b| {
+            let by_count = match order {
+                Order::Common => b.occurrences.cmp(&a.occurrences),
+                Order::Rare => a.occurrences.cmp(&b.occurrences),
+            };
+            // Keep the order stable between loads.
+            by_count.then_with(|| a.samples.cmp(&b.samples))
+        });
+    }
+}
+
+/// Draws the browser window if open.
+pub fn show(
+    egui_ctx: &egui::CtxRef,
+    browser: &mut Browser,
+    palette: &Palette,
+    events: &Sender<generate::Event>,
+) {
+    let mut open = browser.open;
+    egui::Window::new("Stamps")
+        .open(&mut open)
+        .show(egui_ctx, |ui| {
+            ui.horizontal(|ui| {
+                let old_order = browser.order;
+                ui.label("Sort");
+                ui.radio_value(&mut browser.order, Order::Common, "Common first");
+                ui.radio_value(&mut browser.order, Order::Rare, "Rare first");
+                if browser.order != old_order {
+                    browser.sort();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("{} stamps", browser.entries.len()));
+                if browser.modified && ui.button("Apply").clicked() {
+                    events.send(generate::Event::ApplyStampSettings).unwrap();
+                }
+            });
+            let Browser { entries, settings, modified, .. } = &mut *browser;
+            egui::ScrollArea::vertical()
+                .max_height(600.0)
+                .show(ui, |ui| {
+                    for entry in entries.iter() {
+                        let setting = settings.entry(entry.samples.clone())
+                            .or_default();
+                        let old_setting = *setting;
+                        ui.horizontal(|ui| {
+                            inspect::show_samples::<collapse::StampShape>(ui, palette, &entry.samples);
+                            ui.vertical(|ui| {
+                                ui.label(format!("seen {} times", entry.occurrences));
+                                ui.checkbox(&mut setting.enabled, "Enabled");
+                                ui.add(
+                                    egui::Slider::new(&mut setting.weight, 0.01..=100.0)
+                                        .logarithmic(true)
+                                        .text("weight")
+                                );
+                            });
+                        });
+                        ui.separator();
+                        if *setting != old_setting {
+                            *modified = true;
+                        }
+                    }
+                });
+        });
+    browser.open = open;
+}
@@ -5,11 +5,13 @@ use crate::edit;
 use baustein::re::{ ConstAnyShape, ConstShape };
 use rental::rental;
 use rental::RentalError;
+use std::cmp;
 use wfc_3d as wfc;
+use wfc::VoxelId;
 use wfc::stamp::{gather_stamps, StampCollection, StampSpace, Wrapping};
 
 
-type StampShape = ConstAnyShape<3, 3, 3>;
+pub type StampShape = ConstAnyShape<3, 3, 3>;
 
 
 rental! {
@@ -28,11 +30,31 @@ pub use stamps::Stamps;
 
 impl Stamps {
     pub fn from_source(source: StampSpace<edit::Shape>) -> Stamps {
+        Self::from_source_weighted(source, |_samples, occurrences| Some(occurrences))
+    }
+
+    /// `weigh` receives the voxels of each stamp and how often it occurs,
+    /// and returns the weight to use instead, or `None` to leave the stamp out.
+    pub fn from_source_weighted(
+        source: StampSpace<edit::Shape>,
+        weigh: impl Fn(&[VoxelId], usize) -> Option<usize>,
+    ) -> Stamps {
         Self::try_new(
             Box::new(source),
             |source| {
-                let stamps = gather_stamps::<_, _>(&*source, Wrapping);
-                Ok(StampCollection::from_iter(stamps))
+                let mut stamps: Vec<_> = gather_stamps::<_, StampShape>(&*source, Wrapping)
+                    .into_iter()
+                    .filter_map(|(stamp, occurrences)| {
+                        weigh(&stamp.get_samples(), occurrences)
+                            .map(|weight| (stamp, weight))
+                    })
+                    .collect();
+                // Hash map order changes between runs.
+                // Sorting keeps stamp ids the same for the same source.
+                stamps.sort_by_cached_key(|(stamp, weight)| {
+                    (cmp::Reverse(*weight), stamp.get_samples())
+                });
+                Ok(StampCollection::new(stamps))
             }
         ).unwrap_or_else(|_: RentalError<(), _>| panic!("Failed"))
     }
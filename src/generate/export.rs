@@ -0,0 +1,139 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Moving generated scenes out of the generator.
+ */
+
+use baustein::indices::Index;
+use baustein::prefab::PaletteVoxel;
+use baustein::world::FlatPaddedCuboid;
+use wfc_3d as wfc;
+
+use crate::{edit, Palette};
+use crate::generate::{collapse, scene, StampsSource};
+
+// Used traits
+use baustein::traits::{Cuboid, IterableSpace, Space};
+use wfc::palette::Palette as _;
+
+
+/// What to turn voxels into if they aren't decided yet.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Undecided {
+    Empty,
+    /// The first allowed voxel which is solid.
+    Solid,
+    /// The one the most stamps agree on.
+    /// Without stamps, it's the same as Empty.
+    MostLikely,
+}
+
+/// The resource with export choices.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub undecided: Undecided,
+    /// Where the lowest corner of the scene lands in the editor world.
+    pub offset: [i32; 3],
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            undecided: Undecided::Empty,
+            offset: [0, 0, 0],
+        }
+    }
+}
+
+pub type Voxels = FlatPaddedCuboid<PaletteVoxel>;
+
+/// Converts the wave to editor voxels.
+/// Impossible voxels always become empty.
+pub fn to_palette_voxels(
+    wave: &scene::WaveSpace,
+    weights: Option<&FlatPaddedCuboid<wfc::Weights>>,
+    undecided: Undecided,
+    palette: &Palette,
+) -> Voxels {
+    wave.map_index(|index, v| {
+        let v: scene::Superposition = v.into();
+        let mut allowed = v.iter_allowed();
+        match (allowed.next(), allowed.next()) {
+            (None, _) => return PaletteVoxel::EMPTY,
+            (Some(voxel), None) => return voxel,
+            _ => {},
+        };
+        let choice = match undecided {
+            Undecided::Empty => None,
+            Undecided::Solid => v.iter_allowed()
+                .find(|voxel| palette.is_solid(*voxel)),
+            Undecided::MostLikely => weights.and_then(|weights| {
+                let weights = weights.get(index);
+                v.iter_allowed()
+                    .max_by_key(|voxel| weights.get(scene::Ids::to_ref(*voxel)))
+            }),
+        };
+        choice.unwrap_or(PaletteVoxel::EMPTY)
+    })
+    .into()
+}
+
+/// Converts the scene, calculating the likelihood if needed.
+pub fn export(
+    world: &scene::World,
+    stamps: &StampsSource,
+    undecided: Undecided,
+    palette: &Palette,
+) -> Voxels {
+    let wave = world.wave.get_world();
+    let weights = match (undecided, stamps) {
+        (Undecided::MostLikely, StampsSource::Present3x3x3(stamps)) => {
+            Some(collapse::Stamps::rent(stamps, |stamps| {
+                wfc::get_weights(wave, stamps.get_distribution())
+            }))
+        },
+        _ => None,
+    };
+    to_palette_voxels(wave, weights.as_ref(), undecided, palette)
+}
+
+/// Overwrites editor voxels with the scene, placing its corner at `offset`.
+/// Returns how many voxels didn't fit in the editor world.
+pub fn place(voxels: &Voxels, editor: &mut edit::World, offset: Index) -> usize {
+    let mut clipped = 0;
+    voxels.visit_indices(|index| {
+        let target = offset + (index - voxels.get_offset());
+        if let Err(_) = editor.0.set(target, voxels.get(index)) {
+            clipped += 1;
+        }
+    });
+    clipped
+}
+
+/// Creates an editor world containing only the scene.
+pub fn into_editor_world(voxels: &Voxels, offset: Index) -> (edit::World, usize) {
+    let mut world = edit::empty();
+    let clipped = place(voxels, &mut world, offset);
+    (world, clipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SceneConfig;
+
+    #[test]
+    fn undecided() {
+        let palette = Palette::default();
+        let seed = scene::seed(&SceneConfig::default(), &palette);
+        let wave = seed.wave.get_world();
+        let voxels = to_palette_voxels(wave, None, Undecided::Empty, &palette);
+        assert!(voxels.get([0, 0, 0].into()) == PaletteVoxel(1));
+        assert!(voxels.get([0, 1, 0].into()) == PaletteVoxel::EMPTY);
+        assert!(voxels.get([2, 2, 2].into()) == PaletteVoxel::EMPTY);
+
+        let voxels = to_palette_voxels(wave, None, Undecided::Solid, &palette);
+        assert!(voxels.get([0, 1, 0].into()) == PaletteVoxel::EMPTY);
+        assert!(voxels.get([2, 2, 2].into()) != PaletteVoxel::EMPTY);
+    }
+}
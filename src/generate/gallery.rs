@@ -0,0 +1,130 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Many candidates generated from the same starting scene.
+ */
+
+use bevy::ecs::system::ResMut;
+use rand::Rng;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use crate::{GeneratorConfig, Palette, PostprocessConfig};
+use crate::generate::{collapse, oneshot, scene};
+
+
+/// How the next batch gets generated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub count: usize,
+    /// One thread per candidate.
+    pub parallel: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            parallel: true,
+        }
+    }
+}
+
+pub struct Candidate {
+    pub world: scene::World,
+    pub report: oneshot::Report,
+}
+
+/// The resource with all the finished candidates.
+/// Candidates show up in the order they finish.
+pub struct Gallery {
+    pub candidates: Vec<Candidate>,
+    /// How many are still being generated.
+    pub pending: usize,
+    /// Changes whenever the candidates get replaced.
+    pub batch: u64,
+    // Mutex makes this a valid resource.
+    results: Option<Mutex<Receiver<Candidate>>>,
+}
+
+impl Gallery {
+    pub fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            pending: 0,
+            batch: 0,
+            results: None,
+        }
+    }
+
+    /// Drops the previous batch and starts generating a new one.
+    /// Each candidate starts from `start` with its own seed.
+    pub fn start(
+        &mut self,
+        stamps: Arc<collapse::Stamps>,
+        palette: &Palette,
+        start: &scene::World,
+        limits: GeneratorConfig,
+        cleanup: &PostprocessConfig,
+        settings: Settings,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        let mut seeds = start.rng.clone();
+        let starts: Vec<_> = (0..settings.count)
+            .map(|_| start.reseeded(seeds.gen()))
+            .collect();
+        if settings.parallel {
+            for start in starts {
+                let sender = sender.clone();
+                let stamps = stamps.clone();
+                let palette = palette.clone();
+                let cleanup = cleanup.clone();
+                thread::spawn(move || {
+                    let (world, report)
+                        = oneshot::generate(&stamps, &palette, &start, limits, &cleanup);
+                    // Nobody cares if the batch got replaced meanwhile.
+                    let _ = sender.send(Candidate { world, report });
+                });
+            }
+        } else {
+            let palette = palette.clone();
+            let cleanup = cleanup.clone();
+            thread::spawn(move || {
+                for start in starts {
+                    let (world, report)
+                        = oneshot::generate(&stamps, &palette, &start, limits, &cleanup);
+                    if let Err(_) = sender.send(Candidate { world, report }) {
+                        return;
+                    }
+                }
+            });
+        }
+        self.candidates = Vec::new();
+        self.pending = settings.count;
+        self.batch = self.batch.wrapping_add(1);
+        self.results = Some(Mutex::new(receiver));
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            batch: self.batch.wrapping_add(1),
+            ..Self::new()
+        };
+    }
+
+    /// Collects candidates finished since the last call.
+    pub fn receive(&mut self) {
+        if let Some(results) = &self.results {
+            for candidate in results.lock().unwrap().try_iter() {
+                self.candidates.push(candidate);
+                self.pending -= 1;
+            }
+        }
+    }
+}
+
+pub fn collect_candidates(mut gallery: ResMut<Gallery>) {
+    gallery.receive();
+}
@@ -0,0 +1,105 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Past states of the scene, to step back through the collapse.
+ *
+ * Every published state gets stored whole.
+ * Scenes are small, so it's not worth storing diffs.
+ */
+
+use std::collections::VecDeque;
+
+use crate::generate::scene;
+
+
+/// The resource with past states of the scene, oldest first.
+pub struct History {
+    entries: VecDeque<scene::World>,
+    /// The entry currently in the scene.
+    position: usize,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(start: scene::World, capacity: usize) -> Self {
+        let mut entries = VecDeque::with_capacity(capacity);
+        entries.push_back(start);
+        Self {
+            entries,
+            position: 0,
+            // There must be space at least for the current state.
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Adds a state after the current one.
+    /// If the current one is not the newest, the newer ones get replaced.
+    /// That's how branching from an earlier state works.
+    pub fn record(&mut self, world: &scene::World) {
+        self.entries.truncate(self.position + 1);
+        self.entries.push_back(world.clone());
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    /// Forgets everything and starts over.
+    pub fn reset(&mut self, world: &scene::World) {
+        self.entries.clear();
+        self.entries.push_back(world.clone());
+        self.position = 0;
+    }
+
+    /// Moves to an earlier or later state, without forgetting any.
+    pub fn seek(&mut self, position: usize) -> Option<&scene::World> {
+        let world = self.entries.get(position)?;
+        self.position = position;
+        Some(world)
+    }
+
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Palette, SceneConfig};
+
+    #[test]
+    fn branch() {
+        let world = scene::seed(&SceneConfig::default(), &Palette::default());
+        let mut history = History::new(world.clone(), 10);
+        for _ in 0..5 {
+            history.record(&world);
+        }
+        assert_eq!(history.len(), 6);
+        assert_eq!(history.get_position(), 5);
+
+        assert!(history.seek(2).is_some());
+        assert_eq!(history.len(), 6);
+        history.record(&world);
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.get_position(), 3);
+
+        assert!(history.seek(4).is_none());
+        assert_eq!(history.get_position(), 3);
+    }
+
+    #[test]
+    fn capacity() {
+        let world = scene::seed(&SceneConfig::default(), &Palette::default());
+        let mut history = History::new(world.clone(), 3);
+        for _ in 0..5 {
+            history.record(&world);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get_position(), 2);
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Shows what's going on in a single cell of the scene.
+ */
+
+use baustein::indices::Index;
+use baustein::re::ConstShape;
+use bevy::ecs::system::{ Res, ResMut };
+use bevy_egui::{egui, EguiContext};
+use wfc_3d as wfc;
+use wfc::VoxelId;
+use wfc::stamp::{ViewStamp, ST};
+
+use crate::Palette;
+use crate::generate;
+use crate::generate::{collapse, pick, scene, StampsSource};
+
+// Used traits
+use baustein::traits::Space;
+use wfc::palette::Palette as _;
+
+
+/// The resource with the cell being inspected.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Inspected {
+    pub cell: Option<Index>,
+    /// Position of the stamp in the distribution.
+    pub stamp: Option<usize>,
+}
+
+/// Follows the pointer, but stays in place while the pointer is over the UI.
+/// That makes it possible to click through the inspector.
+pub fn follow_cursor(
+    window: Res<generate::Window>,
+    mut egui_ctx: ResMut<EguiContext>,
+    cursor: Res<pick::Cursor>,
+    mut inspected: ResMut<Inspected>,
+) {
+    let over_ui = egui_ctx.try_ctx_for_window(window.0)
+        .map(|ctx| ctx.wants_pointer_input())
+        .unwrap_or(false);
+    if over_ui {
+        return;
+    }
+    let cell = cursor.hit.or(cursor.before);
+    if cell.is_some() && cell != inspected.cell {
+        inspected.cell = cell;
+    }
+}
+
+/// Draws the contents of the info section.
+pub fn show(
+    ui: &mut egui::Ui,
+    inspected: &mut Inspected,
+    world: &scene::World,
+    stamps: &StampsSource,
+    palette: &Palette,
+) {
+    let cell = match inspected.cell {
+        Some(cell) => cell,
+        None => {
+            ui.label("Point at a cell to inspect it");
+            return;
+        },
+    };
+    let wave = world.wave.get_world();
+    let superposition: scene::Superposition = wave.get(cell).into();
+    let allowed: Vec<_> = palette.iter()
+        .filter(|(voxel, _)| superposition.allows(*voxel))
+        .map(|(_, entry)| entry.name.as_str())
+        .collect();
+    let coords: [i32; 3] = cell.into();
+    ui.label(format!("Cell {:?}", coords));
+    ui.label(match allowed.len() {
+        0 => "Nothing allowed".into(),
+        _ => format!("Allowed: {}", allowed.join(", ")),
+    });
+
+    let stamps = match stamps {
+        StampsSource::None => { return; },
+        StampsSource::Present3x3x3(stamps) => stamps,
+    };
+    collapse::Stamps::rent(stamps, |stamps| {
+        let distribution = stamps.get_distribution();
+        let total = stamps.get_total_occurrences();
+        let positions = wfc::get_stamp_positions_containing::<collapse::StampShape, _, { scene::DIMENSIONS }>(
+            wave,
+            cell,
+        );
+        for position in positions {
+            let view = ViewStamp::<collapse::StampShape, _>::new(wave, position);
+            let entropy = wfc::get_superposition_pseudo_entropy(&view, distribution, total);
+            let ids = wfc::get_allowed_stamp_ids(&view, distribution);
+            let coords: [i32; 3] = position.into();
+            ui.collapsing(
+                format!("Stamp at {:?}: {:?}, {} fit", coords, entropy, ids.len()),
+                |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        for id in ids {
+                            let selected = inspected.stamp == Some(id);
+                            if ui.selectable_label(selected, format!("#{}", id)).clicked() {
+                                inspected.stamp = Some(id);
+                            }
+                        }
+                    });
+                },
+            );
+        }
+
+        if let Some((stamp, occurrences)) = inspected.stamp.and_then(|id| distribution.get(id)) {
+            ui.label(format!(
+                "Stamp #{}, seen {} times",
+                inspected.stamp.unwrap(),
+                occurrences,
+            ));
+            show_stamp(ui, palette, stamp);
+        }
+    });
+}
+
+/// Draws horizontal slices from the top, one letter per voxel.
+fn show_stamp<S: ConstShape>(
+    ui: &mut egui::Ui,
+    palette: &Palette,
+    stamp: &ST<'_, collapse::StampShape, S>,
+) {
+    show_samples::<collapse::StampShape>(ui, palette, &stamp.get_samples());
+}
+
+/// Draws horizontal slices of a stamp side by side, from the top,
+/// one letter per voxel.
+/// `samples` are in the order of `Shape` linear indices.
+pub fn show_samples<Shape: ConstShape>(
+    ui: &mut egui::Ui,
+    palette: &Palette,
+    samples: &[VoxelId],
+) {
+    let [width, height, depth] = Shape::ARRAY;
+    ui.horizontal(|ui| {
+        for y in (0..height).rev() {
+            let rows: Vec<String> = (0..depth)
+                .map(|z| {
+                    (0..width)
+                        .map(|x| {
+                            let id = samples[Shape::linearize([x, y, z])];
+                            voxel_letter(palette, scene::Ids::get(id))
+                        })
+                        .collect()
+                })
+                .collect();
+            ui.monospace(rows.join("\n"));
+        }
+    });
+}
+
+/// The first letter of the name.
+/// Lowercase if an earlier voxel already took it.
+fn voxel_letter(palette: &Palette, v: scene::Voxel) -> char {
+    if !palette.is_solid(v) {
+        return '.';
+    }
+    let letter = |v| palette.get_name(v).chars().next().unwrap_or('?');
+    let ours = letter(v);
+    let taken = palette.iter()
+        .take_while(|(other, _)| *other != v)
+        .any(|(other, _)| palette.is_solid(other) && letter(other) == ours);
+    match taken {
+        true => ours.to_lowercase().next().unwrap_or(ours),
+        false => ours,
+    }
+}
@@ -1,15 +1,29 @@
 /*! Stuff related to the world generator and its UI.
  *
  Based on bevy example source. */
+mod browser;
 mod collapse;
+mod export;
+mod gallery;
+mod history;
+mod inspect;
+mod oneshot;
+mod overlay;
+mod pick;
+mod postprocess;
 pub mod render;
 mod scene;
 mod ui;
+mod worker;
+
+pub use postprocess::Pass as PostprocessPass;
 
 use crate::edit;
+use crate::{GeneratorConfig, Palette, PostprocessConfig, SceneConfig};
 use crate::time;
 
 use baustein;
+use baustein::indices::Index;
 use baustein::prefab::{ PaletteIdChunk, PaletteVoxel };
 use baustein::re::ConstPow2Shape;
 use baustein::world::{Cow, FlatPaddedGridCuboid};
@@ -28,9 +42,12 @@ use bevy::{
 };
 
 use bevy_egui;
-use std::sync::Mutex;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::thread;
 use std::time::Instant;
 use wfc_3d as wfc;
 use wfc::wave;
@@ -54,23 +71,59 @@ impl bevy::app::Plugin for CameraPlugin {
     }
 }
 
+/// Requires: `SceneConfig`, `GeneratorConfig`, `PostprocessConfig`, and `Palette` resources.
 pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
         let (ui_sender, ui_receiver) = mpsc::channel::<Event>();
+        let palette = app.world_mut()
+            .get_resource::<Palette>()
+            .expect("Palette missing")
+            .clone();
+        let seed = scene::seed(
+            app.world_mut()
+                .get_resource::<SceneConfig>()
+                .expect("SceneConfig missing"),
+            &palette,
+        );
+        // The first solid voxel is the most useful one to paint with.
+        let brush = palette.iter()
+            .find(|(voxel, _)| palette.is_solid(*voxel))
+            .map(|(voxel, _)| voxel)
+            .unwrap_or(scene::Voxel::EMPTY);
+        let history_length = app.world_mut()
+            .get_resource::<GeneratorConfig>()
+            .expect("GeneratorConfig missing")
+            .history_length;
+        let history = history::History::new(seed.clone(), history_length);
         app
             .add_plugin(CameraPlugin)
-            .insert_resource(scene::seed())
+            .insert_resource(seed)
+            .insert_resource(history)
             .insert_resource(Mutex::new(ui_sender))
             .insert_resource(Mutex::new(ui_receiver))
             .insert_resource(StampsSource::None)
             .insert_resource(Generator::Idle)
+            .insert_resource(overlay::Visible(false))
+            .insert_resource(oneshot::LastReport(None))
+            .insert_resource(gallery::Gallery::new())
+            .insert_resource(gallery::Settings::default())
+            .insert_resource(export::Settings::default())
+            .insert_resource(pick::Cursor::default())
+            .insert_resource(pick::Brush(brush))
+            .insert_resource(inspect::Inspected::default())
+            .insert_resource(browser::Browser::new())
             .add_system_set(
                 SystemSet::on_update(AppState::Done)
                     .with_system(ui::process.system())
                     .with_system(handle_events.system())
-                    .with_system(run_generator.system())
+                    .with_system(receive_snapshots.system())
+                    .with_system(gallery::collect_candidates.system())
+                    .with_system(overlay::update.system())
+                    .with_system(pick::update_cursor.system())
+                    .with_system(pick::paint.system())
+                    .with_system(inspect::follow_cursor.system())
             )
             ;
     }
@@ -78,19 +131,61 @@ impl bevy::app::Plugin for Plugin {
 
 pub enum StampsSource {
     None,
-    Present3x3x3(collapse::Stamps),
+    // Shared with the generator thread.
+    Present3x3x3(Arc<collapse::Stamps>),
 }
 
 pub enum Event {
     StepOne,
+    Run,
+    Pause,
+    /// Stops the generator, keeping the scene as it is.
+    Cancel,
+    /// Collapses the whole scene.
+    Generate,
+    /// Applies cleanup passes to the scene as it is.
+    Postprocess,
+    /// Starts generating a batch of candidates from the scene.
+    GenerateBatch,
+    /// Replaces the scene with the candidate.
+    Pick(usize),
+    /// Replaces a part of the editor world with the candidate.
+    Promote(usize),
+    /// Replaces a part of the editor world with the scene.
+    SendToEditor,
+    /// Saves the scene alone as an editor file.
+    SaveScene(PathBuf),
+    /// Allows only this voxel in the cell.
+    Force(Index, scene::Voxel),
+    /// Disallows this voxel in the cell.
+    Exclude(Index, scene::Voxel),
     LoadStamps,
+    /// Rebuilds the stamps with settings from the stamp browser.
+    ApplyStampSettings,
     Reset,
+    /// Stops the generator and shows an earlier or later state from history.
+    /// Generating from there replaces the later states.
+    Seek(usize),
+    /// Changes the random seed of the scene,
+    /// so that the next step makes a different choice than before.
+    Branch,
 }
 
 pub fn handle_events(
-    source: Res<edit::World>,
+    mut source: ResMut<edit::World>,
+    limits: Res<GeneratorConfig>,
+    scene_config: Res<SceneConfig>,
+    cleanup: Res<PostprocessConfig>,
+    palette: Res<Palette>,
+    batch_settings: Res<gallery::Settings>,
+    mut gallery: ResMut<gallery::Gallery>,
+    export_settings: Res<export::Settings>,
     mut stamps: ResMut<StampsSource>,
+    mut generator: ResMut<Generator>,
     mut world: ResMut<scene::World>,
+    mut report: ResMut<oneshot::LastReport>,
+    mut browser: ResMut<browser::Browser>,
+    mut history: ResMut<history::History>,
     events: Res<Mutex<Receiver<Event>>>,
 ) {
     let events = events.try_lock();
@@ -98,86 +193,243 @@ pub fn handle_events(
         for event in events.try_iter() {
             use Event::*;
             match event {
-                StepOne => generator_step(&*source, &*stamps, &mut *world),
-                LoadStamps => {
-                    let converted_source
-                        = source.0
-                        .map(|v| v.0 as wfc::VoxelId)
-                        .into();
-                    *stamps = StampsSource::Present3x3x3(time!(
-                        collapse::Stamps::from_source(converted_source)
-                    ));
+                StepOne => if let Some(worker) = start_worker(&mut *generator, &*world, &*stamps, &*palette) {
+                    worker.send(worker::Command::Step);
+                    *generator = Generator::Paused(worker);
+                },
+                Run => if let Some(worker) = start_worker(&mut *generator, &*world, &*stamps, &*palette) {
+                    worker.send(worker::Command::Run);
+                    *generator = Generator::Running(worker);
+                },
+                Pause => if let Some(worker) = generator.take_worker() {
+                    worker.send(worker::Command::Pause);
+                    *generator = Generator::Paused(worker);
+                },
+                Cancel => stop_worker(&mut *generator, &mut *world, &mut *history),
+                Generate => if let Some(worker) = start_worker(&mut *generator, &*world, &*stamps, &*palette) {
+                    worker.send(worker::Command::Generate(*limits, cleanup.clone()));
+                    // Busy until the report comes.
+                    *generator = Generator::Running(worker);
+                },
+                Postprocess => if let Some(worker) = start_worker(&mut *generator, &*world, &*stamps, &*palette) {
+                    worker.send(worker::Command::Postprocess(cleanup.clone()));
+                    // Busy until the result comes.
+                    *generator = Generator::Running(worker);
+                },
+                GenerateBatch => if let StampsSource::Present3x3x3(stamps) = &*stamps {
+                    gallery.start(
+                        stamps.clone(),
+                        &*palette,
+                        &*world,
+                        *limits,
+                        &*cleanup,
+                        *batch_settings,
+                    );
+                },
+                Pick(index) => if let Some(candidate) = gallery.candidates.get(index) {
+                    *generator = Generator::Idle;
+                    *world = candidate.world.clone();
+                    history.record(&*world);
+                },
+                Promote(index) => if let Some(candidate) = gallery.candidates.get(index) {
+                    send_to_editor(
+                        &candidate.world,
+                        &*stamps,
+                        &*palette,
+                        *export_settings,
+                        &mut *source,
+                    );
+                },
+                SendToEditor => send_to_editor(
+                    &*world,
+                    &*stamps,
+                    &*palette,
+                    *export_settings,
+                    &mut *source,
+                ),
+                Force(index, voxel) => paint(
+                    &mut *generator,
+                    &*world,
+                    &*stamps,
+                    &*palette,
+                    worker::Command::Force(index, voxel),
+                ),
+                Exclude(index, voxel) => paint(
+                    &mut *generator,
+                    &*world,
+                    &*stamps,
+                    &*palette,
+                    worker::Command::Exclude(index, voxel),
+                ),
+                SaveScene(path) => {
+                    let voxels = export::export(
+                        &*world,
+                        &*stamps,
+                        export_settings.undecided,
+                        &*palette,
+                    );
+                    let (editor, clipped)
+                        = export::into_editor_world(&voxels, export_settings.offset.into());
+                    if clipped > 0 {
+                        eprintln!("{} voxels don't fit in the saved world", clipped);
+                    }
+                    thread::spawn(move ||
+                        edit::save(editor, path)
+                            .unwrap_or_else(|e| eprintln!("Failed to save: {:?}", e))
+                    );
+                },
+                LoadStamps => match scene::to_stamp_source(&*source) {
+                    Ok(converted_source) => {
+                        // The generator would keep using the old stamps.
+                        stop_worker(&mut *generator, &mut *world, &mut *history);
+                        *stamps = StampsSource::Present3x3x3(Arc::new(time!(
+                            browser.load(converted_source)
+                        )));
+                    },
+                    Err(e) => eprintln!("Can't load stamps: {}", e),
+                },
+                ApplyStampSettings => if let Some(new_stamps) = browser.build() {
+                    stop_worker(&mut *generator, &mut *world, &mut *history);
+                    *stamps = StampsSource::Present3x3x3(Arc::new(new_stamps));
                 },
                 Reset => {
-                    *world = scene::seed();
+                    *generator = Generator::Idle;
+                    *world = scene::seed(&*scene_config, &*palette);
+                    history.reset(&*world);
+                    report.0 = None;
+                    gallery.clear();
+                },
+                Seek(position) => {
+                    // Snapshots still in flight would move the history forward again.
+                    *generator = Generator::Idle;
+                    if let Some(past) = history.seek(position) {
+                        *world = past.clone();
+                    }
+                },
+                Branch => {
+                    stop_worker(&mut *generator, &mut *world, &mut *history);
+                    *world = world.reseeded(rand::random());
                 },
             }
         }
     }
 }
 
-#[derive(Clone, Copy)]
 pub enum Generator {
-    Running,
     Idle,
+    Running(worker::Handle),
+    Paused(worker::Handle),
 }
 
-pub fn run_generator(
-    source: Res<edit::World>,
-    stamps: Res<StampsSource>,
-    generator: Res<Generator>,
-    mut world: ResMut<scene::World>,
-) {
-    // Run every available frame. Don't care.
-    match &*generator {
-        Generator::Running => generator_step(&*source, &*stamps, &mut *world),
-        Generator::Idle => {},
+impl Generator {
+    fn get_worker(&self) -> Option<&worker::Handle> {
+        match self {
+            Generator::Idle => None,
+            Generator::Running(worker) | Generator::Paused(worker) => Some(worker),
+        }
+    }
+
+    /// Leaves the generator idle.
+    fn take_worker(&mut self) -> Option<worker::Handle> {
+        match mem::replace(self, Generator::Idle) {
+            Generator::Idle => None,
+            Generator::Running(worker) | Generator::Paused(worker) => Some(worker),
+        }
     }
 }
 
-fn generator_step(
-    source: &edit::World,
+/// Returns the existing worker, or starts a new one on a copy of the scene.
+fn start_worker(
+    generator: &mut Generator,
+    world: &scene::World,
     stamps: &StampsSource,
+    palette: &Palette,
+) -> Option<worker::Handle> {
+    generator.take_worker().or_else(|| match stamps {
+        StampsSource::None => None,
+        StampsSource::Present3x3x3(stamps) => Some(worker::Handle::spawn(
+            world.clone(),
+            stamps.clone(),
+            palette.clone(),
+        )),
+    })
+}
+
+/// Sends a change to the worker without making it run or stop.
+fn paint(
+    generator: &mut Generator,
+    world: &scene::World,
+    stamps: &StampsSource,
+    palette: &Palette,
+    command: worker::Command,
+) {
+    let running = matches!(generator, Generator::Running(_));
+    if let Some(worker) = start_worker(generator, world, stamps, palette) {
+        worker.send(command);
+        *generator = match running {
+            true => Generator::Running(worker),
+            false => Generator::Paused(worker),
+        };
+    }
+}
+
+/// Keeps the last published state of the scene.
+fn stop_worker(
+    generator: &mut Generator,
     world: &mut scene::World,
+    history: &mut history::History,
 ) {
-    match &*stamps {
-        StampsSource::None => {},
-        StampsSource::Present3x3x3(stamps) => {
-            // This should probably be relegated to another thread,
-            // but the other thread still needs mutable access to the same world
-            // that is being rendered and interacted with.
-            // That means a copy-on-write world should be used,
-            // because it lets the "base" reference read-only,
-            // but until that happens, the only alternative is to copy the whole world.
-            collapse::Stamps::rent(
-                stamps,
-                |stamps| {
-                    // This is kind of expensive,
-                    // but if we don't make sure all new collapses are resolved,
-                    // then the entropy finder is going to ignore them and cause nonsense results.
-                    if world.wave.collapse(&world.wave.get_extent(), stamps) == true {
-                        return;
-                    }
-                    let candidate = wfc::find_lowest_pseudo_entropy(
-                        world.wave.get_world(),
-                        stamps.get_distribution(),
-                        stamps.get_total_occurrences(),
-                    );
-                    println!("Stamp index: {:?}", candidate);
-                    if let Some(index) = candidate {
-                        let stamp = wfc::choose_stamp_weighted(
-                            wfc::stamp::ViewStamp::new(&world.wave.get_world(), index),
-                            &stamps,
-                            &mut world.rng,
-                        );
-                        println!("Stamp content: {:?}", stamp);
-                        // Trigger collapse
-                        world.wave.limit_stamp(index, &stamp, &stamps).unwrap();
-                    }
-                },
-            );
-        },
+    let snapshots = generator.take_worker()
+        .map(|w| w.drain())
+        .unwrap_or_default();
+    for snapshot in snapshots {
+        history.record(&snapshot.world);
+        *world = snapshot.world;
+    }
+}
+
+fn send_to_editor(
+    world: &scene::World,
+    stamps: &StampsSource,
+    palette: &Palette,
+    settings: export::Settings,
+    editor: &mut edit::World,
+) {
+    let voxels = export::export(world, stamps, settings.undecided, palette);
+    let clipped = export::place(&voxels, editor, settings.offset.into());
+    if clipped > 0 {
+        eprintln!("{} voxels don't fit in the editor world", clipped);
+    }
+}
+
+/// Shows what the generator thread is doing.
+pub fn receive_snapshots(
+    mut generator: ResMut<Generator>,
+    mut world: ResMut<scene::World>,
+    mut report: ResMut<oneshot::LastReport>,
+    mut history: ResMut<history::History>,
+) {
+    let snapshots = match generator.get_worker() {
+        Some(worker) => worker.drain(),
+        None => { return; },
+    };
+    // Every snapshot goes into history, even if only the last one gets shown.
+    for snapshot in &snapshots {
+        history.record(&snapshot.world);
+    }
+    let snapshot = match snapshots.into_iter().last() {
+        Some(snapshot) => snapshot,
+        None => { return; },
     };
+    *world = snapshot.world;
+    if let Some(r) = snapshot.report {
+        report.0 = Some(r);
+    }
+    if let (false, Generator::Running(_)) = (snapshot.running, &*generator) {
+        // Ran out of things to do.
+        let worker = generator.take_worker().unwrap();
+        *generator = Generator::Paused(worker);
+    }
 }
                     
 pub struct Window(WindowId);
@@ -385,7 +637,8 @@ fn setup_pipeline(
             transform: eye_look_at_target_transform(eye, target),
             ..Default::default()
         })
-        .insert(RenderLayers::layer(1));
+        .insert(RenderLayers::layer(1))
+        .insert(pick::CameraTag);
 
     app_state.set(AppState::Done).unwrap();
 }
@@ -0,0 +1,66 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Generating the whole scene in one go.
+ */
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+use wfc_3d as wfc;
+use wfc::wave;
+
+use crate::{GeneratorConfig, Palette, PostprocessConfig};
+use crate::generate::{collapse, postprocess, scene};
+
+
+/// How the last generation went.
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    pub attempts: usize,
+    pub outcome: wfc::Outcome,
+}
+
+/// The most recent report, for the UI.
+pub struct LastReport(pub Option<Report>);
+
+/// Collapses the scene starting from `start`.
+/// On contradiction, starts over from `start` with a new seed,
+/// until it runs out of attempts or time.
+/// A collapsed scene gets cleaned up afterwards.
+pub fn generate(
+    stamps: &collapse::Stamps,
+    palette: &Palette,
+    start: &scene::World,
+    limits: GeneratorConfig,
+    cleanup: &PostprocessConfig,
+) -> (scene::World, Report) {
+    let deadline = Instant::now() + Duration::from_millis(limits.time_budget_ms);
+    let mut seeds = start.rng.clone();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        // Each attempt gets its own seed, so that it can be repeated on its own.
+        let mut rng = StdRng::seed_from_u64(seeds.gen());
+        let (space, outcome) = collapse::Stamps::rent(stamps, |stamps| {
+            wfc::execute_weighted(
+                stamps,
+                start.wave.get_world().clone(),
+                &mut rng,
+                deadline,
+            )
+        });
+        let world = scene::World {
+            wave: wave::Naive::new(space),
+            rng,
+        };
+        match outcome {
+            wfc::Outcome::Contradiction if attempts < limits.max_attempts => {},
+            wfc::Outcome::Collapsed => {
+                let world = postprocess::run(cleanup, palette, stamps, &world, deadline);
+                return (world, Report { attempts, outcome });
+            },
+            outcome => return (world, Report { attempts, outcome }),
+        }
+    }
+}
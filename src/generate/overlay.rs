@@ -0,0 +1,114 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Shows the pseudo-entropy of each stamp position over the scene.
+ * Rendering is done by `analyze::render`, just like stress.
+ */
+
+use baustein::indices::VoxelUnits;
+use bevy::math::Vec3;
+use bevy::ecs::entity::Entity;
+use bevy::ecs::query::With;
+use bevy::ecs::system::{ Commands, Query, Res };
+use bevy::render::camera::RenderLayers;
+use bevy::transform::components::Transform;
+use float_ord::FloatOrd;
+use std::collections::HashMap;
+use wfc_3d as wfc;
+use wfc::PseudoEntropy;
+
+use crate::analyze::render::{ Analyzed, StressChunk, Voxel };
+use crate::generate::{collapse, scene, StampsSource};
+
+// Used traits
+use baustein::traits::Cuboid as Extent;
+
+
+/// Whether the entropy overlay is shown.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Visible(pub bool);
+
+/// Marks the overlay chunk, to get rid of it when the overlay changes.
+pub struct Tag;
+
+/// Markers are spread over chunks with this stride.
+/// It keeps them clear of the padding on chunk edges, which doesn't get meshed.
+const CHUNK_STRIDE: i32 = 16;
+
+/// The highest value an open position gets.
+/// Keeps the gradient clear of the colour of impossible positions.
+const OPEN_MAX: f32 = 128.0;
+
+/// Impossible positions are red, collapsed ones are not shown,
+/// and open ones get more red the higher their entropy.
+/// Each marker sits in the centre of its stamp.
+/// Only recalculated when the scene, the stamps or the visibility change.
+pub fn update(
+    mut commands: Commands,
+    visible: Res<Visible>,
+    stamps: Res<StampsSource>,
+    world: Res<scene::World>,
+    overlays: Query<Entity, With<Tag>>,
+) {
+    if !(world.is_changed() || stamps.is_changed() || visible.is_changed()) {
+        return;
+    }
+    for e in overlays.iter() {
+        commands.entity(e).despawn()
+    }
+    if !visible.0 {
+        return;
+    }
+    let stamps = match &*stamps {
+        StampsSource::None => { return; },
+        StampsSource::Present3x3x3(stamps) => stamps,
+    };
+    let wave = world.wave.get_world();
+    let entropies = collapse::Stamps::rent(stamps, |stamps| {
+        wfc::get_pseudo_entropies(
+            wave,
+            stamps.get_distribution(),
+            stamps.get_total_occurrences(),
+        )
+    });
+
+    let max = entropies.iter()
+        .filter_map(|(_index, entropy)| match entropy {
+            PseudoEntropy::Open(v) => Some(FloatOrd(*v)),
+            _ => None,
+        })
+        .max()
+        .map(|v| v.0)
+        .filter(|v| *v > 0.0)
+        .unwrap_or(1.0);
+
+    // Chunks start at 0 to keep their indices the same as mesh coordinates.
+    // The placement is done with the transform.
+    let offset = wave.get_offset();
+    let mut chunks = HashMap::new();
+    for (index, entropy) in entropies {
+        let voxel = match entropy {
+            PseudoEntropy::Impossible => Voxel::Stressed(256.0),
+            PseudoEntropy::Collapsed => Voxel::Empty,
+            PseudoEntropy::Open(v) => Voxel::Stressed(v / max * OPEN_MAX),
+        };
+        // Relative to the scene, the first centre lands right after the padding.
+        let [x, y, z] = (index - offset).0;
+        let center = [x + 1, y + 1, z + 1];
+        let chunk = center.map(|c| (c - 1).div_euclid(CHUNK_STRIDE));
+        let within = [0, 1, 2].map(|i| center[i] - chunk[i] * CHUNK_STRIDE);
+        chunks.entry(chunk)
+            .or_insert_with(|| StressChunk::new([0, 0, 0].into()))
+            .set(within.into(), voxel)
+            .unwrap();
+    }
+    for (chunk, voxels) in chunks {
+        let shift = VoxelUnits(chunk.map(|c| c * CHUNK_STRIDE));
+        let origin: Vec3 = (offset + shift).into();
+        commands.spawn()
+            .insert(Analyzed(voxels))
+            .insert(Transform::from_translation(origin))
+            .insert(RenderLayers::layer(1))
+            .insert(Tag);
+    }
+}
@@ -0,0 +1,134 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Pointing at cells in the generator window, and painting constraints on them.
+ */
+
+use baustein::indices::Index;
+use bevy::ecs::query::With;
+use bevy::ecs::system::{ Query, Res, ResMut };
+use bevy::input::Input;
+use bevy::input::mouse::MouseButton;
+use bevy::render::camera::Camera;
+use bevy::transform::components::Transform;
+use bevy::window::Windows;
+use bevy_egui::EguiContext;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use crate::Palette;
+use crate::generate;
+use crate::generate::scene;
+use crate::geometry::{ray_from_window_point, Ray3};
+
+// Used traits
+use baustein::traits::{Cuboid, Space};
+
+
+/// Designates the generator window camera.
+pub struct CameraTag;
+
+/// The cells under the mouse pointer in the generator window.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Cursor {
+    /// The first solid cell.
+    pub hit: Option<Index>,
+    /// The last cell before hitting anything solid.
+    /// If nothing was hit, the last cell inside the scene.
+    pub before: Option<Index>,
+}
+
+/// The voxel to paint with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Brush(pub scene::Voxel);
+
+pub fn update_cursor(
+    window: Res<generate::Window>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &Transform), With<CameraTag>>,
+    world: Res<scene::World>,
+    palette: Res<Palette>,
+    mut cursor: ResMut<Cursor>,
+) {
+    let window = match windows.get(window.0) {
+        Some(w) => w,
+        None => { return; },
+    };
+    let camera = cameras.iter().next();
+    // The position is only there when the pointer is inside this window.
+    *cursor = match (window.cursor_position(), camera) {
+        (Some(point), Some((camera, transform))) => {
+            let ray = ray_from_window_point(
+                point,
+                (window.width(), window.height()),
+                transform.compute_matrix(),
+                camera.projection_matrix,
+            );
+            march(ray, world.wave.get_world(), &palette)
+        },
+        _ => Cursor::default(),
+    };
+}
+
+/// Walks along the ray in small steps.
+fn march(ray: Ray3, wave: &scene::WaveSpace, palette: &Palette) -> Cursor {
+    const STEP: f32 = 0.05;
+    const MAX_DISTANCE: f32 = 200.0;
+
+    let direction = ray.direction.normalize();
+    let mut before = None;
+    let mut previous = None;
+    for i in 0..((MAX_DISTANCE / STEP) as usize) {
+        let point = ray.origin + direction * (i as f32 * STEP);
+        let index: Index = point.floor().into();
+        if previous == Some(index) {
+            continue;
+        }
+        previous = Some(index);
+        if !contains(wave, index) {
+            continue;
+        }
+        let voxel: scene::Superposition = wave.get(index).into();
+        if voxel.iter_allowed().all(|v| palette.is_solid(v)) {
+            return Cursor { hit: Some(index), before };
+        }
+        before = Some(index);
+    }
+    Cursor { hit: None, before }
+}
+
+fn contains(space: &impl Cuboid, index: Index) -> bool {
+    let start = space.get_offset();
+    let end = space.get_beyond_opposite_corner();
+    (0..3).all(|i| index[i] >= start[i] && index[i] < end[i])
+}
+
+/// Left click forces the brush voxel in front of what's under the pointer.
+/// Right click excludes the brush voxel from what's under the pointer.
+pub fn paint(
+    window: Res<generate::Window>,
+    mut egui_ctx: ResMut<EguiContext>,
+    buttons: Res<Input<MouseButton>>,
+    cursor: Res<Cursor>,
+    brush: Res<Brush>,
+    events: Res<Mutex<Sender<generate::Event>>>,
+) {
+    let over_ui = egui_ctx.try_ctx_for_window(window.0)
+        .map(|ctx| ctx.wants_pointer_input())
+        .unwrap_or(false);
+    if over_ui {
+        return;
+    }
+    let event = if buttons.just_pressed(MouseButton::Left) {
+        cursor.before.map(|index| generate::Event::Force(index, brush.0))
+    } else if buttons.just_pressed(MouseButton::Right) {
+        cursor.hit
+            .or(cursor.before)
+            .map(|index| generate::Event::Exclude(index, brush.0))
+    } else {
+        None
+    };
+    if let Some(event) = event {
+        events.lock().unwrap().send(event).unwrap();
+    }
+}
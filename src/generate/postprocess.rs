@@ -0,0 +1,298 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Cleaning up generated scenes.
+ *
+ * Passes only look at cells which are already decided,
+ * and leave the undecided ones alone.
+ */
+
+use baustein::indices::{Index, VoxelUnits};
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::Instant;
+use wfc_3d as wfc;
+use wfc::wave;
+
+use crate::{Palette, PostprocessConfig};
+use crate::generate::{collapse, scene};
+
+// Used traits
+use baustein::traits::{Cuboid, IterableSpace, Space};
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum Pass {
+    /// Empties solid voxels which don't reach ground through other solid voxels.
+    RemoveIslands,
+    /// Fills enclosed empty spaces of up to `max_volume` voxels
+    /// with the material surrounding them the most.
+    FillCavities { max_volume: usize },
+}
+
+/// Touching by faces.
+const NEIGHBOURS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+fn neighbours(index: Index) -> impl Iterator<Item=Index> {
+    NEIGHBOURS.iter().map(move |offset| index + VoxelUnits(*offset))
+}
+
+fn contains(wave: &scene::WaveSpace, index: Index) -> bool {
+    let start = wave.get_offset();
+    let end = wave.get_beyond_opposite_corner();
+    start.x() <= index.x() && index.x() < end.x()
+        && start.y() <= index.y() && index.y() < end.y()
+        && start.z() <= index.z() && index.z() < end.z()
+}
+
+/// The voxel the cell collapsed into, if any.
+fn get_decided(wave: &scene::WaveSpace, index: Index) -> Option<scene::Voxel> {
+    let v: scene::Superposition = wave.get(index).into();
+    let mut allowed = v.iter_allowed();
+    match (allowed.next(), allowed.next()) {
+        (Some(voxel), None) => Some(voxel),
+        _ => None,
+    }
+}
+
+/// Groups cells for which `belongs` is true into ones touching by faces.
+fn find_components<F>(wave: &scene::WaveSpace, belongs: F) -> Vec<Vec<Index>>
+    where F: Fn(Index) -> bool
+{
+    let mut visited = HashSet::new();
+    let mut components = Vec::new();
+    wave.visit_indices(|start| {
+        if visited.contains(&start) || !belongs(start) {
+            return;
+        }
+        visited.insert(start);
+        let mut component = Vec::new();
+        let mut pending = vec![start];
+        while let Some(index) = pending.pop() {
+            component.push(index);
+            for neighbour in neighbours(index) {
+                if contains(wave, neighbour)
+                    && !visited.contains(&neighbour)
+                    && belongs(neighbour)
+                {
+                    visited.insert(neighbour);
+                    pending.push(neighbour);
+                }
+            }
+        }
+        components.push(component);
+    });
+    components
+}
+
+/// The most common solid voxel around an empty component.
+/// None if the component is open to the outside or to undecided cells.
+fn get_surrounding(
+    wave: &scene::WaveSpace,
+    palette: &Palette,
+    component: &[Index],
+) -> Option<scene::Voxel> {
+    let mut counts = [0usize; scene::DIMENSIONS as usize];
+    for index in component {
+        for neighbour in neighbours(*index) {
+            if !contains(wave, neighbour) {
+                return None;
+            }
+            match get_decided(wave, neighbour) {
+                None => return None,
+                // Part of the component.
+                Some(voxel) if !palette.is_solid(voxel) => {},
+                Some(voxel) => counts[voxel.0 as usize] += 1,
+            }
+        }
+    }
+    counts.iter()
+        .enumerate()
+        // Ties go to the voxel listed first.
+        .max_by_key(|(id, count)| (**count, std::cmp::Reverse(*id)))
+        .filter(|(_id, count)| **count > 0)
+        .map(|(id, _count)| scene::Voxel(id as u8))
+}
+
+/// Returns what the pass would change, without changing anything.
+pub fn find_changes(
+    pass: Pass,
+    palette: &Palette,
+    wave: &scene::WaveSpace,
+) -> Vec<(Index, scene::Voxel)> {
+    match pass {
+        Pass::RemoveIslands => {
+            let is_solid = |index| match get_decided(wave, index) {
+                Some(voxel) => palette.is_solid(voxel),
+                None => false,
+            };
+            find_components(wave, is_solid)
+                .into_iter()
+                .filter(|component| {
+                    !component.iter()
+                        .filter_map(|index| get_decided(wave, *index))
+                        .any(|voxel| palette.is_ground(voxel))
+                })
+                .flatten()
+                .map(|index| (index, scene::Voxel::EMPTY))
+                .collect()
+        },
+        Pass::FillCavities { max_volume } => {
+            let is_empty = |index| match get_decided(wave, index) {
+                Some(voxel) => !palette.is_solid(voxel),
+                None => false,
+            };
+            find_components(wave, is_empty)
+                .into_iter()
+                .filter(|component| component.len() <= max_volume)
+                .filter_map(|component| {
+                    let voxel = get_surrounding(wave, palette, &component)?;
+                    Some(component.into_iter().map(move |index| (index, voxel)))
+                })
+                .flatten()
+                .collect()
+        },
+    }
+}
+
+fn apply(wave: &mut scene::WaveSpace, changes: &[(Index, scene::Voxel)]) {
+    for (index, voxel) in changes {
+        let value: scene::Superposition = [*voxel].as_slice().into();
+        // Changes come from inside the wave.
+        wave.set(*index, value.into()).unwrap();
+    }
+}
+
+/// Frees cells next to the changes and collapses them again,
+/// so that the changes blend in according to the stamps.
+/// Returns None if the stamps can't agree with the changes.
+///
+/// Only the freed cells get decided.
+/// Other undecided cells stay open, apart from what the freed cells rule out.
+fn recollapse(
+    stamps: &collapse::Stamps,
+    palette: &Palette,
+    wave: &scene::WaveSpace,
+    changes: &[(Index, scene::Voxel)],
+    rng: &mut StdRng,
+    deadline: Instant,
+) -> Option<scene::WaveSpace> {
+    let changed: HashSet<Index> = changes.iter().map(|(index, _)| *index).collect();
+    let mut freed = wave.clone();
+    let free = scene::any_of(palette);
+    let mut freed_cells = HashSet::new();
+    for index in &changed {
+        for neighbour in neighbours(*index) {
+            // Neighbours outside the scene don't need freeing.
+            if !changed.contains(&neighbour) && contains(wave, neighbour) {
+                freed.set(neighbour, free.into()).unwrap();
+                freed_cells.insert(neighbour);
+            }
+        }
+    }
+    collapse::Stamps::rent(stamps, |stamps| {
+        // This decides every cell of the scene,
+        // but the choices only matter for the freed ones.
+        let (collapsed, outcome) = wfc::execute_weighted(stamps, freed.clone(), rng, deadline);
+        match outcome {
+            wfc::Outcome::Collapsed => {},
+            _ => return None,
+        }
+        let mut local = freed;
+        for index in &freed_cells {
+            local.set(*index, collapsed.get(*index)).unwrap();
+        }
+        // The collapse agreed with the other cells,
+        // so this only narrows them down, and never contradicts.
+        let mut local = wave::Naive::new(local);
+        let extent = local.get_extent();
+        while local.collapse(&extent, stamps) {}
+        Some(local.into_space())
+    })
+}
+
+/// Runs the configured passes in order.
+/// If re-collapsing fails, the pass result is kept as is.
+pub fn run(
+    config: &PostprocessConfig,
+    palette: &Palette,
+    stamps: &collapse::Stamps,
+    world: &scene::World,
+    deadline: Instant,
+) -> scene::World {
+    let mut world = world.clone();
+    for pass in &config.passes {
+        let mut space = world.wave.get_world().clone();
+        let changes = find_changes(*pass, palette, &space);
+        if changes.is_empty() {
+            continue;
+        }
+        apply(&mut space, &changes);
+        if config.recollapse {
+            if let Some(collapsed)
+                = recollapse(stamps, palette, &space, &changes, &mut world.rng, deadline)
+            {
+                space = collapsed;
+            }
+        }
+        world.wave = wave::Naive::new(space);
+    }
+    world
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SceneConfig;
+
+    fn decided_scene(palette: &Palette) -> scene::WaveSpace {
+        let world = scene::seed(&SceneConfig::default(), palette);
+        let grass = palette.find("Grass").unwrap();
+        let mut wave = world.wave.get_world().clone();
+        let mut cells = Vec::new();
+        wave.visit_indices(|index| {
+            let voxel = if index.y() < 0 { grass }
+                else { scene::Voxel::EMPTY };
+            cells.push((index, voxel));
+        });
+        apply(&mut wave, &cells);
+        wave
+    }
+
+    #[test]
+    fn islands() {
+        let palette = Palette::default();
+        let wood = palette.find("Wood").unwrap();
+        let mut wave = decided_scene(&palette);
+        apply(&mut wave, &[
+            ([0, 0, 0].into(), wood),
+            ([0, 3, 0].into(), wood),
+        ]);
+        let changes = find_changes(Pass::RemoveIslands, &palette, &wave);
+        assert_eq!(changes, vec![([0, 3, 0].into(), scene::Voxel::EMPTY)]);
+    }
+
+    #[test]
+    fn cavities() {
+        let palette = Palette::default();
+        let grass = palette.find("Grass").unwrap();
+        let mut wave = decided_scene(&palette);
+        let hole = [0, -2, 0].into();
+        apply(&mut wave, &[(hole, scene::Voxel::EMPTY)]);
+        let pass = Pass::FillCavities { max_volume: 1 };
+        let changes = find_changes(pass, &palette, &wave);
+        assert_eq!(changes, vec![(hole, grass)]);
+        // The air above touches the edge of the scene.
+        let pass = Pass::FillCavities { max_volume: 1000 };
+        let changes = find_changes(pass, &palette, &wave);
+        assert_eq!(changes, vec![(hole, grass)]);
+    }
+}
@@ -1,172 +1,303 @@
 /*
  * SPDX-License-Identifier: LGPL-3.0-or-later
  */
-use baustein::indices::{to_i32_arr, VoxelUnits};
-use baustein::prefab::PaletteVoxel;
-use baustein::re::{ ConstPow2Shape, ConstShape };
-use baustein::render::{ generate_greedy_buffer_fast, mesh_from_quads, MeshMaterial };
+use baustein::indices::{to_i32_arr, ChunkIndex, VoxelUnits};
+use baustein::re::ConstAnyShape;
+use baustein::render::{
+    create_voxel_mesh_bundle, despawn_dirty, generate_greedy_buffer_fast,
+    generate_greedy_buffer_runtime, get_chunk_mesh_offset, mesh_from_quads, Look, Looks,
+    MeshMaterial, TextureState,
+};
 use baustein::traits::Space;
-use baustein::world::FlatPaddedGridCuboid;
+use baustein::world::{DirtyChunks, FlatPaddedCuboid, FlatPaddedGridCuboid};
 use bevy::app;
 use bevy::app::AppBuilder;
-use bevy::asset::{ Assets, AssetServer, Handle };
+use bevy::asset::Assets;
 use bevy::ecs::entity::Entity;
 use bevy::ecs::query::With;
-use bevy::ecs::schedule::{ State, SystemSet };
-use bevy::ecs::system::{ Commands, Query, Res, ResMut };
+use bevy::ecs::schedule::SystemSet;
+use bevy::ecs::system::{ Commands, Local, Query, Res, ResMut };
 use bevy::math::Vec3;
 use bevy::render::camera::RenderLayers;
 use bevy::render::mesh::Mesh;
-use bevy::render::texture::Texture;
 use bevy::transform::components::Transform;
 use block_mesh;
-use block_mesh::{ greedy_quads, GreedyQuadsBuffer, MergeVoxel, UnorientedQuad, RIGHT_HANDED_Y_UP_CONFIG };
-use feldspar::prelude::{ create_voxel_mesh_bundle, spawn_array_material, ArrayMaterial, VoxelRenderAssets};
+use block_mesh::{ MergeVoxel, UnorientedQuad };
+use wfc_3d as wfc;
 
-use crate::generate::scene;
-use crate::generate::scene::{SceneShape, World};
+use crate::Palette;
+use crate::generate::{collapse, gallery, scene, StampsSource};
+use crate::generate::scene::World;
 
 // Used traits
 use baustein::traits::Cuboid as Extent;
 use bevy::prelude::IntoSystem;
+use wfc_3d::palette::Palette as _;
 
 
-/// Requires: `LoadingTexture` resource.
+/// Requires: `baustein::render::Plugin`.
 pub struct Plugin;
 
 impl app::Plugin for Plugin {
     fn build(&self, app: &mut AppBuilder) {
         app
-            .add_state(TextureState::Loading)
-            .add_system_set(
-                SystemSet::on_enter(TextureState::Loading)
-                    .with_system(start_loading_render_assets.system()),
-            )
-            .add_system_set(
-                SystemSet::on_update(TextureState::Loading)
-                    .with_system(wait_for_assets_loaded.system()),
-            )
+            .insert_resource(RenderMode::Collapsed)
             .add_system_set(
                 SystemSet::on_update(TextureState::Ready)
-                    .with_system(update_meshes.system()),
+                    .with_system(update_meshes.system())
+                    .with_system(update_candidate_meshes.system()),
             )
             ;
     }
 }
 
-// Older version needed for block_mesh
-type BlockMeshShape = SceneShape;
+/// How superpositions are shown in the generator window.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderMode {
+    /// Only voxels that collapsed into solid.
+    Collapsed,
+    /// Voxels which are more likely solid than empty,
+    /// with materials blended according to the stamps that still fit.
+    Likelihood,
+}
 
-/// Marks which meshes should despawn
-pub struct MeshTag;
+/// Marks which meshes should despawn, and which chunk of the scene they show.
+pub struct SceneMesh(ChunkIndex);
 
-#[derive(Eq, Clone, Copy, Default, Debug)]
-struct Voxel(scene::Superposition);
+/// Marks meshes of candidates from the gallery.
+pub struct CandidateMesh;
 
-impl PartialEq for Voxel {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
-}
+/// A chunk with a layer of neighbouring voxels on each side.
+type ChunkMeshShape = ConstAnyShape<18, 18, 18>;
+
+/// Looks of all possibilities already mixed together.
+/// None when nothing gets shown.
+#[derive(Eq, PartialEq, Clone, Copy, Default, Debug)]
+struct Blended(Option<Look>);
 
-impl MergeVoxel for Voxel {
+impl MergeVoxel for Blended {
     type MergeValue = Self;
     fn merge_value(&self) -> Self {
         *self
     }
 }
 
-impl block_mesh::Voxel for Voxel {
+impl block_mesh::Voxel for Blended {
     fn is_empty(&self) -> bool {
-        self.0.allows(scene::Voxel::Empty)
+        self.0.is_none()
     }
     fn is_opaque(&self) -> bool {
-        !self.0.allows(scene::Voxel::Empty)
+        !self.is_empty()
+    }
+}
+
+/// Mixes looks according to their weights.
+/// Textures don't mix, so only a single look keeps its texture.
+fn blend(parts: impl Iterator<Item=(Look, f32)>) -> Blended {
+    let parts: Vec<_> = parts.filter(|(_, weight)| *weight > 0.0).collect();
+    match parts.as_slice() {
+        [] => Blended(None),
+        [(look, _)] => Blended(Some(*look)),
+        parts => {
+            let total: f32 = parts.iter().map(|(_, weight)| weight).sum();
+            let mut colour = [0.0; 4];
+            for (look, weight) in parts {
+                for (mixed, channel) in colour.iter_mut().zip(look.colour) {
+                    *mixed += channel as f32 * weight / total;
+                }
+            }
+            Blended(Some(Look::from_colour(colour.map(|c| c.round() as u8))))
+        },
     }
 }
 
-/// Shows only voxels that collapsed into solid.
-fn to_material_empty(v: Voxel) -> [u8; 4] {
-    use scene::Voxel::*;
-    if v.0.allows(Empty) {
-        [0; 4]
+/// Shows only voxels that can't be anything but solid.
+/// Each material that's still possible is shown.
+fn to_material_solid(palette: &Palette, looks: &Looks, v: scene::Superposition) -> Blended {
+    if v.iter_allowed().all(|voxel| palette.is_solid(voxel)) {
+        blend(v.iter_allowed().map(|voxel| (looks.get(voxel), 1.0)))
     } else {
-        let f = |t| {
-            if v.0.allows(t) { 1 }
-            else { 0 }
-        };
-        [f(Grass), f(Concrete), f(Wood), f(Glass)]
+        Blended(None)
     }
 }
 
-pub fn update_meshes(
+/// Shows voxels which are more likely solid than empty.
+/// Each material is as strong as its likelihood.
+/// Voxels where no stamp fits any more are not shown at all.
+fn to_material_likely(palette: &Palette, looks: &Looks, w: wfc::Weights) -> Blended {
+    let p = |voxel| w.get_probability(scene::Ids::to_ref(voxel)).unwrap_or(0.0);
+    let p_empty: f32 = palette.iter()
+        .filter(|(_, entry)| entry.empty)
+        .map(|(voxel, _)| p(voxel))
+        .sum();
+    if w.get_total() > 0 && p_empty < 0.5 {
+        blend(
+            palette.iter()
+                .filter(|(_, entry)| !entry.empty)
+                .map(|(voxel, _)| (looks.get(voxel), p(voxel)))
+        )
+    } else {
+        Blended(None)
+    }
+}
+
+/// What's currently shown, to find out what changed.
+#[derive(Default)]
+struct Shown {
+    scene: Option<FlatPaddedCuboid<Blended>>,
+    /// The gallery batch whose candidates are shown.
+    batch: u64,
+    candidates: usize,
+}
+
+/// Re-meshes only the chunks of the scene which look different than before.
+fn update_meshes(
     mut commands: Commands,
     mesh_material: Res<MeshMaterial>,
+    mode: Res<RenderMode>,
+    stamps: Res<StampsSource>,
+    palette: Res<Palette>,
+    looks: Res<Looks>,
     space: Res<World>,
+    mut shown: Local<Shown>,
     mut meshes: ResMut<Assets<Mesh>>,
-    edit_meshes: Query<Entity, With<MeshTag>>,
+    scene_meshes: Query<(Entity, &SceneMesh)>,
 ) {
-    // Get rid of all meshes
-    for cm in edit_meshes.iter() {
-        commands.entity(cm).despawn()
+    let changed = space.is_changed() || mode.is_changed() || stamps.is_changed()
+        || palette.is_changed() || looks.is_changed();
+    if !changed && shown.scene.is_some() {
+        return;
     }
-    // And create the occupied ones again.
-    // Wasteful, I know. I'm testing!
-    let space = space.wave.get_world().map(|v| Voxel(v.into()));
-    let space = FlatPaddedGridCuboid::<Voxel, SceneShape>::new_from_space(&space, space.get_offset());
-    let quads = generate_greedy_buffer_fast(&space);
-    let material_lookup = |quad: &UnorientedQuad| {
-        let v = space.get(space.get_offset() + VoxelUnits(to_i32_arr(quad.minimum)));
-        let material = to_material_empty(v);
-        [material, material, material, material]
+    let wave = space.wave.get_world();
+    let blended = match (*mode, &*stamps) {
+        (RenderMode::Likelihood, StampsSource::Present3x3x3(stamps)) => {
+            let weights = collapse::Stamps::rent(stamps, |stamps| {
+                wfc::get_weights(wave, stamps.get_distribution())
+            });
+            let space = weights.map(|w| to_material_likely(&palette, &looks, w));
+            FlatPaddedCuboid::<Blended>::from(space)
+        },
+        // Without stamps, there's nothing to base likelihood on.
+        _ => {
+            let space = wave.map(|v| to_material_solid(&palette, &looks, v.into()));
+            FlatPaddedCuboid::<Blended>::from(space)
+        },
     };
-    let mesh = mesh_from_quads(quads, &space, material_lookup);
-    if let Some((mesh, materials)) = mesh {
-        commands
-            .spawn_bundle(
-                create_voxel_mesh_bundle(
-                    mesh,
-                    materials,
-                    mesh_material.0.clone(),
-                    &mut meshes,
+
+    let dirty = match &shown.scene {
+        Some(previous)
+            if previous.get_offset() == blended.get_offset()
+                && previous.get_dimensions() == blended.get_dimensions()
+            => DirtyChunks::from_difference(&blended, previous),
+        previous => {
+            let mut dirty = DirtyChunks::new();
+            if let Some(previous) = previous {
+                dirty.mark_cuboid(previous.get_offset(), previous.get_dimensions());
+            }
+            dirty.mark_cuboid(blended.get_offset(), blended.get_dimensions());
+            dirty
+        },
+    };
+    despawn_dirty(&mut commands, &dirty, &scene_meshes, |mesh| mesh.0);
+    for index in dirty.iter() {
+        let chunk = FlatPaddedGridCuboid::<_, ChunkMeshShape>::new_from_space(
+            &blended,
+            get_chunk_mesh_offset(index),
+        );
+        let quads = generate_greedy_buffer_fast(&chunk);
+        let mesh = mesh_from_quads(quads, &chunk, |quad| look_lookup(&chunk, quad));
+        if let Some((mesh, looks)) = mesh {
+            commands
+                .spawn_bundle(
+                    create_voxel_mesh_bundle(
+                        mesh,
+                        looks,
+                        &mesh_material,
+                        &mut meshes,
+                    )
                 )
-            )
-            .insert(Transform::from_translation(space.get_offset().into()))
-            .insert(MeshTag)
-            .insert(RenderLayers::layer(1))
-            ;
+                .insert(Transform::from_translation(chunk.get_offset().into()))
+                .insert(SceneMesh(index))
+                .insert(RenderLayers::layer(1))
+                ;
+        }
     }
+    shown.scene = Some(blended);
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
-pub enum TextureState {
-    Loading,
-    Ready,
+/// Spawns candidates as they arrive.
+/// The meshes stay until the batch gets replaced.
+fn update_candidate_meshes(
+    mut commands: Commands,
+    mesh_material: Res<MeshMaterial>,
+    palette: Res<Palette>,
+    looks: Res<Looks>,
+    gallery: Res<gallery::Gallery>,
+    mut shown: Local<Shown>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    candidate_meshes: Query<Entity, With<CandidateMesh>>,
+) {
+    if gallery.batch != shown.batch || palette.is_changed() || looks.is_changed() {
+        for entity in candidate_meshes.iter() {
+            commands.entity(entity).despawn()
+        }
+        shown.batch = gallery.batch;
+        shown.candidates = 0;
+    }
+
+    // Candidates go in a row next to the scene.
+    for (i, candidate) in gallery.candidates.iter().enumerate().skip(shown.candidates) {
+        let wave = candidate.world.wave.get_world();
+        let space = wave.map(|v| to_material_solid(&palette, &looks, v.into()));
+        let space = FlatPaddedCuboid::<Blended>::from(space);
+        spawn_mesh(
+            &mut commands,
+            &space,
+            get_candidate_shift(i, space.get_dimensions()[0]),
+            &mesh_material,
+            &mut meshes,
+        );
+    }
+    shown.candidates = gallery.candidates.len();
 }
 
-/// A unique type for a resource containing the texture handle for this module.
-pub struct LoadingTexture(pub Handle<Texture>);
+/// Quads are indexed relative to the corner of the space.
+fn look_lookup<S: Space<Voxel=Blended> + Extent>(space: &S, quad: &UnorientedQuad) -> [Look; 4] {
+    let look = space.get(space.get_offset() + VoxelUnits(to_i32_arr(quad.minimum))).0
+        // Empty voxels don't get quads.
+        .unwrap_or(Look::MISSING);
+    [look, look, look, look]
+}
 
-fn start_loading_render_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.insert_resource(LoadingTexture(
-        asset_server.load("grass_rock_snow_dirt/base_color.png"),
-    ));
+/// Where the candidate is shown relative to the scene.
+pub fn get_candidate_shift(index: usize, width: usize) -> Vec3 {
+    let spacing = width as f32 + 2.0;
+    Vec3::new((index + 1) as f32 * spacing, 0.0, 0.0)
 }
 
-// From feldspar
-fn wait_for_assets_loaded(
-    commands: Commands,
-    loading_texture: Res<LoadingTexture>,
-    textures: ResMut<Assets<Texture>>,
-    array_materials: ResMut<Assets<ArrayMaterial>>,
-    mut state: ResMut<State<TextureState>>,
+fn spawn_mesh(
+    commands: &mut Commands,
+    space: &FlatPaddedCuboid<Blended>,
+    shift: Vec3,
+    mesh_material: &MeshMaterial,
+    meshes: &mut Assets<Mesh>,
 ) {
-    if textures.get(&loading_texture.0).is_some() {
-        let params = VoxelRenderAssets {
-            mesh_base_color: loading_texture.0.clone(),
-            image_count: 4,
-        };
-        spawn_array_material::<MeshMaterial>(&params, commands, array_materials, textures);
-        state.set(TextureState::Ready).unwrap();
+    let quads = generate_greedy_buffer_runtime(space);
+    let mesh = mesh_from_quads(quads, space, |quad| look_lookup(space, quad));
+    if let Some((mesh, looks)) = mesh {
+        let origin: Vec3 = space.get_offset().into();
+        commands
+            .spawn_bundle(
+                create_voxel_mesh_bundle(
+                    mesh,
+                    looks,
+                    mesh_material,
+                    meshes,
+                )
+            )
+            .insert(Transform::from_translation(origin + shift))
+            .insert(CandidateMesh)
+            .insert(RenderLayers::layer(1))
+            ;
     }
 }
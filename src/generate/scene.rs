@@ -1,112 +1,116 @@
 /*
  * SPDX-License-Identifier: LGPL-3.0-or-later
  */
-use baustein::re::ConstAnyShape;
-use baustein::world::FlatPaddedGridCuboid;
-use block_mesh;
-use block_mesh::MergeVoxel;
+use baustein::indices::Index;
+use baustein::prefab::PaletteVoxel;
 use rand::rngs::StdRng;
+use std::error::Error;
 use wfc_3d as wfc;
 use wfc::wave;
+use wfc::stamp::StampSpace;
+
+use crate::{edit, Palette, SceneConfig};
+use crate::palette::MAX_ENTRIES;
 
 
-use baustein::traits::Space;
 use rand::SeedableRng;
-// this is actually used. Rustc is just complaining.
-use wfc_3d::palette::Palette as _;
+// Used traits
+use baustein::traits::{IterableSpace, Space};
+use wfc::palette::Palette as _;
 
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum Voxel {
-    Empty,
-    Grass,
-    Concrete,
-    Wood,
-    Glass,
-}
+/// Same ids as in the editor.
+pub type Voxel = PaletteVoxel;
 
-impl Default for Voxel {
-    fn default() -> Self {
-        Voxel::Empty
-    }
-}
+/// As many distinct voxel possibilities as a palette can have.
+pub const DIMENSIONS: u8 = crate::palette::MAX_ENTRIES as u8;
 
-impl MergeVoxel for Voxel {
-    type MergeValue = Self;
-    fn merge_value(&self) -> Self {
-        *self
-    }
+pub type Superposition = wfc::palette::Superposition<Voxel, Ids, DIMENSIONS>;
+
+/// The storage of the scene. Its size comes from `SceneConfig`.
+pub type WaveSpace = wfc::SuperpositionSpace<DIMENSIONS>;
+
+/// A wrapper over a mundane chunk, for the purpose of becoming the Bevy resource.
+#[derive(Clone)]
+pub struct World{
+    pub wave: wave::Naive<WaveSpace, DIMENSIONS>,
+    pub rng: StdRng,
 }
 
-impl block_mesh::Voxel for Voxel {
-    fn is_empty(&self) -> bool {
-        match self {
-            Voxel::Empty => true,
-            _ => false,
-        }
-    }
-    fn is_opaque(&self) -> bool {
-        match self {
-            Voxel::Empty => true,
-            _ => false,
+impl World {
+    /// The same wave, but with a different random generator.
+    pub fn reseeded(&self, seed: u64) -> Self {
+        World {
+            wave: self.wave.clone(),
+            rng: StdRng::seed_from_u64(seed),
         }
     }
 }
 
-/// 5 dimensions - 5 distinct voxel possibilities.
-pub type Superposition = wfc::palette::Superposition<Voxel, Palette, 5>;
-
-pub type SceneShape = ConstAnyShape<10, 10, 10>;
-
-/// A wrapper over a mundane chunk, for the purpose of becoming the Bevy resource.
-pub struct World{
-    pub wave: wave::Naive<SceneShape, 5>,
-    pub rng: StdRng,
+/// Allows every voxel from the palette, and nothing else.
+pub fn any_of(palette: &Palette) -> Superposition {
+    let voxels: Vec<_> = palette.iter().map(|(voxel, _)| voxel).collect();
+    voxels.as_slice().into()
 }
 
 /// Create a seed world with some collapse involved
-pub fn seed() -> World {
-    let extent = FlatPaddedGridCuboid::<(), SceneShape>::new([-5, -5, -5].into());
-    use Voxel::*;
-    let world: FlatPaddedGridCuboid<wfc::Superposition<5>, SceneShape>
-        = extent.map_index(|i, _| {
-            if i == [0,1,0].into() { [Empty].as_slice().into() }
-            else if i == [0,0,0].into() { [Grass].as_slice().into() }
-            else { Superposition::free() }
-        })
-        .map(|v: Superposition| v.into())
-        .into();
+pub fn seed(config: &SceneConfig, palette: &Palette) -> World {
+    let [x, y, z] = config.dimensions;
+    let offset: Index = [-(x as i32 / 2), -(y as i32 / 2), -(z as i32 / 2)].into();
+    let mut world = WaveSpace::new(offset, config.dimensions);
+    let free = any_of(palette);
+    for index in world.indices() {
+        world.set(index, free.into()).unwrap();
+    }
+    for cell in &config.seed {
+        let allowed: Vec<_> = cell.allowed.iter()
+            .filter_map(|name| {
+                let voxel = palette.find(name);
+                if voxel.is_none() {
+                    eprintln!("Seed voxel {:?} is not in the palette", name);
+                }
+                voxel
+            })
+            .collect();
+        let allowed: Superposition = allowed.as_slice().into();
+        world.set(cell.position.into(), allowed.into())
+            .unwrap_or_else(|_| eprintln!("Seed cell {:?} is outside the scene", cell.position));
+    }
     World {
         wave: wave::Naive::new(world),
         rng: StdRng::seed_from_u64(0),
     }
 }
 
-/// Converts between wfc representation and the one for rendering.
+/// Converts editor voxels into a source of stamps.
+/// Fails if a voxel doesn't fit in a superposition,
+/// which can happen with files made for a bigger palette.
+pub fn to_stamp_source(source: &edit::World) -> Result<StampSpace<edit::Shape>, Box<dyn Error>> {
+    let too_big = source.0.voxels()
+        .find(|(_index, voxel)| voxel.0 as usize >= MAX_ENTRIES);
+    if let Some((index, voxel)) = too_big {
+        return Err(format!(
+            "Voxel {:?} at {:?} is beyond the {} palette entries the generator supports",
+            voxel,
+            index,
+            MAX_ENTRIES,
+        ).into());
+    }
+    Ok(source.0.map(Ids::to_ref).into())
+}
+
+/// Converts between wfc representation and palette voxels.
+/// The ids are the same.
+/// Ids must be below `DIMENSIONS`.
 #[derive(Clone, Copy, Debug)]
-pub struct Palette {}
+pub struct Ids {}
 
-impl wfc::palette::Palette<Voxel> for Palette {
+impl wfc::palette::Palette<Voxel> for Ids {
     fn get(id: wfc::VoxelId) -> Voxel {
-        use Voxel::*;
-        match id {
-            0 => Empty,
-            1 => Grass,
-            2 => Concrete,
-            3 => Wood,
-            4 => Glass,
-            _ => panic!(),
-        }
+        PaletteVoxel(id)
     }
     fn to_ref(v: Voxel) -> wfc::VoxelId {
-        use Voxel::*;
-        match v {
-            Empty => 0,
-            Grass => 1,
-            Concrete => 2,
-            Wood => 3,
-            Glass => 4,
-        }
+        v.0
     }
 }
 
@@ -116,25 +120,63 @@ mod test {
 
     use crate::edit;
     use crate::generate::collapse;
+    use baustein::re::ConstAnyShape;
+    use baustein::traits::{Cuboid, Space};
+    use baustein::world::FlatPaddedGridCuboid;
+    use crate::SeedCell;
+
+    use wfc_3d::palette::Palette as _;
 
     #[test]
     fn sanity() {
-        for id in 0..5 {
-            let v = Palette::get(id);
-            let r = Palette::to_ref(v);
+        for id in 0..DIMENSIONS {
+            let v = Ids::get(id);
+            let r = Ids::to_ref(v);
             assert_eq!(r, id);
         }
     }
 
+    #[test]
+    fn stamp_source_ids() {
+        let mut source = edit::empty();
+        source.0.set([1, 2, 3].into(), PaletteVoxel(DIMENSIONS - 1)).unwrap();
+        assert!(to_stamp_source(&source).is_ok());
+        source.0.set([1, 2, 3].into(), PaletteVoxel(DIMENSIONS)).unwrap();
+        assert!(to_stamp_source(&source).is_err());
+    }
+
+    #[test]
+    fn seed_size() {
+        let config = SceneConfig {
+            dimensions: [20, 8, 20],
+            seed: vec![SeedCell {
+                position: [0, -4, 0],
+                allowed: vec!["Grass".into(), "Wood".into()],
+            }],
+        };
+        let palette = Palette::default();
+        let world = seed(&config, &palette);
+        let wave = world.wave.get_world();
+        assert_eq!(wave.get_dimensions(), [20, 8, 20]);
+        let cell: Superposition = wave.get([0, -4, 0].into()).into();
+        let grass = palette.find("Grass").unwrap();
+        let wood = palette.find("Wood").unwrap();
+        assert_eq!(cell, [grass, wood].as_slice().into());
+        let free: Superposition = wave.get([9, 3, 9].into()).into();
+        assert_eq!(free, any_of(&palette));
+        assert_eq!(free.iter_allowed().count(), 5);
+    }
+
     pub type SceneShape = ConstAnyShape<5, 5, 5>;
 
-    fn seed_test() -> FlatPaddedGridCuboid<wfc::Superposition<5>, SceneShape> {
+    fn seed_test() -> FlatPaddedGridCuboid<wfc::Superposition<DIMENSIONS>, SceneShape> {
         let extent = FlatPaddedGridCuboid::<(), SceneShape>::new([-4, -4, -4].into());
-        use Voxel::*;
+        let palette = Palette::default();
+        let grass = palette.find("Grass").unwrap();
         extent.map_index(|i, _| {
-            if i == [0,1,0].into() { [Empty].as_slice().into() }
-            else if i == [0,0,0].into() { [Grass].as_slice().into() }
-            else { Superposition::free() }
+            if i == [0,1,0].into() { [Voxel::EMPTY].as_slice().into() }
+            else if i == [0,0,0].into() { [grass].as_slice().into() }
+            else { any_of(&palette) }
         })
         .map(|v: Superposition| v.into())
         .into()
@@ -145,7 +187,7 @@ mod test {
     fn seed_entropy() {
         let converted_source
             = edit::floor().0
-            .map(|v| v.0 as wfc::VoxelId)
+            .map(Ids::to_ref)
             .into();
         let stamps = collapse::Stamps::from_source(converted_source);
         let world = seed_test();
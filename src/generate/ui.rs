@@ -15,7 +15,17 @@ use std::thread;
 use crate::CursorRay;
 use crate::generate;
 use crate::generate::{Generator, StampsSource};
+use crate::{GeneratorConfig, Palette, SceneConfig, SeedCell};
+use crate::generate::browser;
 use crate::generate::collapse;
+use crate::generate::export;
+use crate::generate::gallery;
+use crate::generate::history;
+use crate::generate::inspect;
+use crate::generate::oneshot;
+use crate::generate::pick;
+use crate::generate::overlay;
+use crate::generate::render::RenderMode;
 use crate::generate::scene;
 
 
@@ -32,7 +42,19 @@ pub fn process(
     window_id: Res<generate::Window>,
     stamps: Res<StampsSource>,
     mut egui_ctx: ResMut<EguiContext>,
-    mut generation_state: ResMut<Generator>,
+    generation_state: Res<Generator>,
+    mut render_mode: ResMut<RenderMode>,
+    mut overlay: ResMut<overlay::Visible>,
+    // Grouped to stay within the limit on system parameters.
+    (mut limits, mut scene_config): (ResMut<GeneratorConfig>, ResMut<SceneConfig>),
+    (report, history): (Res<oneshot::LastReport>, Res<history::History>),
+    gallery: Res<gallery::Gallery>,
+    mut batch_settings: ResMut<gallery::Settings>,
+    mut export_settings: ResMut<export::Settings>,
+    mut brush: ResMut<pick::Brush>,
+    mut inspected: ResMut<inspect::Inspected>,
+    (world, palette): (Res<scene::World>, Res<Palette>),
+    mut browser: ResMut<browser::Browser>,
     //mut slice_state: ResMut<slice::State>,
     events: Res<Mutex<Sender<generate::Event>>>,
 ) {
@@ -42,10 +64,62 @@ pub fn process(
         Some(k) => k,
         None => {return;},
     };
-    let new_state = process_panel(ctx, &*stamps, old_state, &mut *generation_state, &events);
+    let mut new_mode = *render_mode;
+    let mut new_overlay = *overlay;
+    let mut new_limits = *limits;
+    let mut new_scene_config = scene_config.clone();
+    let mut new_batch_settings = *batch_settings;
+    let mut new_export_settings = *export_settings;
+    let mut new_brush = *brush;
+    let mut new_inspected = *inspected;
+    let new_state = process_panel(
+        ctx,
+        &*stamps,
+        old_state,
+        &*generation_state,
+        &mut new_mode,
+        &mut new_overlay,
+        &mut new_limits,
+        &mut new_scene_config,
+        &*report,
+        &*history,
+        &*gallery,
+        &mut new_batch_settings,
+        &mut new_export_settings,
+        &mut new_brush,
+        &mut new_inspected,
+        &*world,
+        &*palette,
+        &mut *browser,
+        &events,
+    );
     if new_state != old_state {
         //*slice_state = new_state.slice_state;
     }
+    if new_mode != *render_mode {
+        *render_mode = new_mode;
+    }
+    if new_overlay != *overlay {
+        *overlay = new_overlay;
+    }
+    if new_limits != *limits {
+        *limits = new_limits;
+    }
+    if new_scene_config != *scene_config {
+        *scene_config = new_scene_config;
+    }
+    if new_batch_settings != *batch_settings {
+        *batch_settings = new_batch_settings;
+    }
+    if new_export_settings != *export_settings {
+        *export_settings = new_export_settings;
+    }
+    if new_brush != *brush {
+        *brush = new_brush;
+    }
+    if new_inspected != *inspected {
+        *inspected = new_inspected;
+    }
 }
 
 /// Draws panel and sends messages.
@@ -53,29 +127,210 @@ fn process_panel(
     egui_ctx: &egui::CtxRef,
     stamps: &StampsSource,
     mut ui_state: State,
-    mut generation_state: &mut Generator,
+    generation_state: &Generator,
+    render_mode: &mut RenderMode,
+    overlay: &mut overlay::Visible,
+    limits: &mut GeneratorConfig,
+    scene_config: &mut SceneConfig,
+    report: &oneshot::LastReport,
+    history: &history::History,
+    gallery: &gallery::Gallery,
+    batch_settings: &mut gallery::Settings,
+    export_settings: &mut export::Settings,
+    brush: &mut pick::Brush,
+    inspected: &mut inspect::Inspected,
+    world: &scene::World,
+    palette: &Palette,
+    browser: &mut browser::Browser,
     events: &Sender<generate::Event>,
 ) -> State {
     egui::SidePanel::left("side_panel")
         .show(egui_ctx, |ui| {
             ui.heading("Scene");
+            ui.horizontal(|ui| {
+                ui.label("Size");
+                for dimension in scene_config.dimensions.iter_mut() {
+                    ui.add(egui::DragValue::new(dimension).clamp_range(3..=64));
+                }
+            });
+            ui.label("Seed cells");
+            let mut removed = None;
+            for (i, cell) in scene_config.seed.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    for coord in cell.position.iter_mut() {
+                        ui.add(egui::DragValue::new(coord));
+                    }
+                    for (_, entry) in palette.iter() {
+                        let name = &entry.name;
+                        let allowed = cell.allowed.contains(name);
+                        let label = ui.selectable_label(allowed, name.get(..1).unwrap_or("?"))
+                            .on_hover_text(name);
+                        if label.clicked() {
+                            if allowed {
+                                cell.allowed.retain(|n| n != name);
+                            } else {
+                                cell.allowed.push(name.clone());
+                            }
+                        }
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                scene_config.seed.remove(i);
+            }
+            if ui.button("Add seed cell").clicked() {
+                let ground = palette.iter()
+                    .find(|(voxel, _)| palette.is_ground(*voxel))
+                    .or_else(|| palette.iter().find(|(voxel, _)| palette.is_solid(*voxel)));
+                scene_config.seed.push(SeedCell {
+                    position: [0, 0, 0],
+                    allowed: ground.map(|(_, entry)| entry.name.clone()).into_iter().collect(),
+                });
+            }
             if ui.button("Reset to seed").clicked() {
                 events.send(generate::Event::Reset).unwrap();
             }
+            ui.label("Show");
+            ui.radio_value(render_mode, RenderMode::Collapsed, "Collapsed");
+            ui.radio_value(render_mode, RenderMode::Likelihood, "Likelihood");
+            ui.checkbox(&mut overlay.0, "Entropy overlay");
+
+            ui.label("Undecided voxels become");
+            {
+                use export::Undecided;
+                let undecided = &mut export_settings.undecided;
+                ui.radio_value(undecided, Undecided::Empty, "Empty");
+                ui.radio_value(undecided, Undecided::Solid, "Solid");
+                ui.radio_value(undecided, Undecided::MostLikely, "Most likely");
+            }
+            ui.horizontal(|ui| {
+                ui.label("Editor offset");
+                for coord in export_settings.offset.iter_mut() {
+                    ui.add(egui::DragValue::new(coord));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("To editor").clicked() {
+                    events.send(generate::Event::SendToEditor).unwrap();
+                }
+                if ui.button("Save…").clicked() {
+                    let sender = events.clone();
+                    thread::spawn(move || {
+                        let path = rfd::FileDialog::new()
+                            .add_filter("Domostroj scene", &["domo"])
+                            .set_directory(".")
+                            .save_file();
+                        if let Some(path) = path {
+                            sender
+                                .send(generate::Event::SaveScene(path))
+                                .unwrap_or_else(|e| eprintln!("Can't save: {:?}", e));
+                        }
+                    });
+                }
+            });
+            ui.heading("Paint");
+            ui.label("Left click: force in front. Right click: exclude.");
+            ui.horizontal_wrapped(|ui| {
+                for (voxel, entry) in palette.iter() {
+                    ui.radio_value(&mut brush.0, voxel, &entry.name);
+                }
+            });
+
             ui.heading("Generator");
             if ui.button("1 Step").clicked() {
                 events.send(generate::Event::StepOne).unwrap();
             }
 
-            match (*generation_state, stamps) {
+            match (generation_state, stamps) {
                 (_, StampsSource::None) => {},
-                (Generator::Idle, _) => if ui.button("Run").clicked() {
-                    *generation_state = Generator::Running;
+                (Generator::Idle, _) | (Generator::Paused(_), _) => if ui.button("Run").clicked() {
+                    events.send(generate::Event::Run).unwrap();
                 },
-                (Generator::Running, _) => if ui.button("Pause").clicked() {
-                    *generation_state = Generator::Idle;
+                (Generator::Running(_), _) => if ui.button("Pause").clicked() {
+                    events.send(generate::Event::Pause).unwrap();
                 },
             };
+            match generation_state {
+                Generator::Idle => {},
+                _ => if ui.button("Stop").clicked() {
+                    events.send(generate::Event::Cancel).unwrap();
+                },
+            };
+
+            if let StampsSource::Present3x3x3(_) = stamps {
+                if ui.button("Generate").clicked() {
+                    events.send(generate::Event::Generate).unwrap();
+                }
+                if ui.button("Clean up").clicked() {
+                    events.send(generate::Event::Postprocess).unwrap();
+                }
+            }
+            ui.add(
+                egui::Slider::new(&mut limits.time_budget_ms, 100..=60000)
+                    .text("time budget [ms]")
+            );
+            ui.add(
+                egui::Slider::new(&mut limits.max_attempts, 1..=100)
+                    .text("max attempts")
+            );
+            if let Some(report) = report.0 {
+                ui.label(format!(
+                    "{:?} after {} attempts",
+                    report.outcome,
+                    report.attempts,
+                ));
+            }
+
+            ui.heading("History");
+            ui.horizontal(|ui| {
+                let old_position = history.get_position();
+                let mut position = old_position;
+                if ui.button("<").clicked() {
+                    position = position.saturating_sub(1);
+                }
+                ui.add(
+                    egui::Slider::new(&mut position, 0..=(history.len() - 1))
+                        .text("step")
+                );
+                if ui.button(">").clicked() {
+                    position = cmp::min(position + 1, history.len() - 1);
+                }
+                if position != old_position {
+                    events.send(generate::Event::Seek(position)).unwrap();
+                }
+            });
+            if ui.button("Branch with new seed").clicked() {
+                events.send(generate::Event::Branch).unwrap();
+            }
+
+            ui.heading("Candidates");
+            ui.add(
+                egui::Slider::new(&mut batch_settings.count, 1..=16)
+                    .text("count")
+            );
+            ui.checkbox(&mut batch_settings.parallel, "In parallel");
+            if let StampsSource::Present3x3x3(_) = stamps {
+                if ui.button("Generate candidates").clicked() {
+                    events.send(generate::Event::GenerateBatch).unwrap();
+                }
+            }
+            if gallery.pending > 0 {
+                ui.label(format!("{} in progress", gallery.pending));
+            }
+            for (i, candidate) in gallery.candidates.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}: {:?}", i + 1, candidate.report.outcome));
+                    if ui.button("Use").clicked() {
+                        events.send(generate::Event::Pick(i)).unwrap();
+                    }
+                    if ui.button("To editor").clicked() {
+                        events.send(generate::Event::Promote(i)).unwrap();
+                    }
+                });
+            }
 
             ui.heading("Stamp source");
             match stamps {
@@ -92,8 +347,12 @@ fn process_panel(
             if ui.button("Update from editor").clicked() {
                 events.send(generate::Event::LoadStamps).unwrap();
             }
+            if let StampsSource::Present3x3x3(_) = stamps {
+                ui.checkbox(&mut browser.open, "Browse stamps");
+            }
 
             ui.heading("Info");
+            inspect::show(ui, inspected, world, stamps, palette);
             /*
             {
                 let slice_level = &mut ui_state.slice_state.slice_height.0;
@@ -106,5 +365,6 @@ fn process_panel(
                 );
             }*/
         });
+    browser::show(egui_ctx, browser, palette, events);
     ui_state
 }
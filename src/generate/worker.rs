@@ -0,0 +1,270 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Runs the generator on its own thread.
+ *
+ * The thread owns its wave, and publishes copies of it.
+ * Copying the whole scene is wasteful, so while running,
+ * copies go out only every now and then, and not after every step.
+ */
+
+use baustein::indices::Index;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use wfc_3d as wfc;
+
+use crate::{GeneratorConfig, Palette, PostprocessConfig};
+use crate::generate::{collapse, oneshot, postprocess, scene};
+
+// Used traits
+use baustein::traits::Space;
+
+
+/// How long cleaning up on request may spend on re-collapsing.
+const CLEANUP_TIME: Duration = Duration::from_secs(10);
+
+/// While running, snapshots go out at most this often.
+/// Anything faster than frames gets thrown away anyway.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum Command {
+    /// Make a single step, then wait.
+    Step,
+    /// Keep making steps until paused or there's nothing left to collapse.
+    Run,
+    Pause,
+    /// Collapse everything at once and clean up, then wait.
+    Generate(GeneratorConfig, PostprocessConfig),
+    /// Clean up the scene as it is.
+    Postprocess(PostprocessConfig),
+    /// Allow only this voxel in the cell.
+    Force(Index, scene::Voxel),
+    /// Disallow this voxel in the cell.
+    Exclude(Index, scene::Voxel),
+}
+
+/// The state of the generator after a step.
+pub struct Snapshot {
+    pub world: scene::World,
+    /// False if the generator stopped on its own, or was never running.
+    pub running: bool,
+    /// Only present after `Command::Generate`.
+    pub report: Option<oneshot::Report>,
+}
+
+/// Controls the generator thread.
+/// The thread stops when this gets dropped.
+pub struct Handle {
+    // Mutexes make this a valid resource.
+    commands: Mutex<Sender<Command>>,
+    snapshots: Mutex<Receiver<Snapshot>>,
+}
+
+impl Handle {
+    /// Starts a paused generator.
+    pub fn spawn(
+        world: scene::World,
+        stamps: Arc<collapse::Stamps>,
+        palette: Palette,
+    ) -> Self {
+        let (commands, command_receiver) = mpsc::channel();
+        let (snapshot_sender, snapshots) = mpsc::channel();
+        thread::Builder::new()
+            .name("generator".into())
+            .spawn(move || run(world, stamps, palette, command_receiver, snapshot_sender))
+            .unwrap();
+        Self {
+            commands: Mutex::new(commands),
+            snapshots: Mutex::new(snapshots),
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        // If the thread is gone, there's nobody left to care.
+        let _ = self.commands.lock().unwrap().send(command);
+    }
+
+    /// Returns all snapshots since the last call, oldest first.
+    pub fn drain(&self) -> Vec<Snapshot> {
+        self.snapshots.lock().unwrap().try_iter().collect()
+    }
+}
+
+fn run(
+    mut world: scene::World,
+    stamps: Arc<collapse::Stamps>,
+    palette: Palette,
+    commands: Receiver<Command>,
+    snapshots: Sender<Snapshot>,
+) {
+    let mut publisher = Publisher::new(snapshots);
+    let mut running = false;
+    loop {
+        let command = if running {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            // Nothing to do until told otherwise.
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            }
+        };
+        match command {
+            Some(Command::Run) => { running = true; },
+            Some(Command::Step) => { running = false; },
+            Some(Command::Pause) => {
+                running = false;
+                if !publisher.catch_up(&world, running) {
+                    return;
+                }
+                continue;
+            },
+            Some(Command::Generate(limits, cleanup)) => {
+                running = false;
+                let (generated, report)
+                    = oneshot::generate(&stamps, &palette, &world, limits, &cleanup);
+                world = generated;
+                if !publisher.publish(&world, running, Some(report)) {
+                    return;
+                }
+                continue;
+            },
+            Some(Command::Postprocess(cleanup)) => {
+                running = false;
+                let deadline = Instant::now() + CLEANUP_TIME;
+                world = postprocess::run(&cleanup, &palette, &stamps, &world, deadline);
+                if !publisher.publish(&world, running, None) {
+                    return;
+                }
+                continue;
+            },
+            Some(Command::Force(index, voxel)) => {
+                let value = [voxel].as_slice().into();
+                set(&mut world, &stamps, index, value);
+                if !publisher.publish(&world, running, None) {
+                    return;
+                }
+                continue;
+            },
+            Some(Command::Exclude(index, voxel)) => {
+                let current: scene::Superposition = world.wave.get_world().get(index).into();
+                let allowed: Vec<_> = current.iter_allowed()
+                    .filter(|v| *v != voxel)
+                    .collect();
+                set(&mut world, &stamps, index, allowed.as_slice().into());
+                if !publisher.publish(&world, running, None) {
+                    return;
+                }
+                continue;
+            },
+            None => {},
+        };
+        let progress = step(&mut world, &stamps);
+        running = running && progress;
+        if !publisher.publish_step(&world, running) {
+            return;
+        }
+    }
+}
+
+/// Sends snapshots, and remembers when it last did.
+struct Publisher {
+    snapshots: Sender<Snapshot>,
+    last: Option<Instant>,
+    /// Some steps were made since the last snapshot.
+    behind: bool,
+}
+
+impl Publisher {
+    fn new(snapshots: Sender<Snapshot>) -> Self {
+        Self { snapshots, last: None, behind: false }
+    }
+
+    /// Returns false if nobody listens any more.
+    fn publish(
+        &mut self,
+        world: &scene::World,
+        running: bool,
+        report: Option<oneshot::Report>,
+    ) -> bool {
+        self.last = Some(Instant::now());
+        self.behind = false;
+        let snapshot = Snapshot { world: world.clone(), running, report };
+        self.snapshots.send(snapshot).is_ok()
+    }
+
+    /// While running, holds the step back if the last snapshot is recent.
+    /// The step when running stops always goes out.
+    fn publish_step(&mut self, world: &scene::World, running: bool) -> bool {
+        let recent = self.last
+            .map(|last| last.elapsed() < PUBLISH_INTERVAL)
+            .unwrap_or(false);
+        if running && recent {
+            self.behind = true;
+            true
+        } else {
+            self.publish(world, running, None)
+        }
+    }
+
+    /// Publishes the steps which were held back, if any.
+    fn catch_up(&mut self, world: &scene::World, running: bool) -> bool {
+        if self.behind {
+            self.publish(world, running, None)
+        } else {
+            true
+        }
+    }
+}
+
+/// Changes the cell and propagates the consequences.
+fn set(
+    world: &mut scene::World,
+    stamps: &collapse::Stamps,
+    index: Index,
+    value: scene::Superposition,
+) {
+    collapse::Stamps::rent(stamps, |stamps| {
+        world.wave.set(index, value.into(), stamps)
+            .unwrap_or_else(|e| eprintln!("{:?} {:?}", e, index));
+    })
+}
+
+/// Returns false if there was nothing left to collapse.
+pub fn step(world: &mut scene::World, stamps: &collapse::Stamps) -> bool {
+    collapse::Stamps::rent(
+        stamps,
+        |stamps| {
+            // This is kind of expensive,
+            // but if we don't make sure all new collapses are resolved,
+            // then the entropy finder is going to ignore them and cause nonsense results.
+            if world.wave.collapse(&world.wave.get_extent(), stamps) == true {
+                return true;
+            }
+            let candidate = wfc::find_lowest_pseudo_entropy(
+                world.wave.get_world(),
+                stamps.get_distribution(),
+                stamps.get_total_occurrences(),
+            );
+            if let Some(index) = candidate {
+                let stamp = wfc::choose_stamp_weighted(
+                    wfc::stamp::ViewStamp::new(&world.wave.get_world(), index),
+                    &stamps,
+                    &mut world.rng,
+                );
+                // Trigger collapse
+                world.wave.limit_stamp(index, &stamp, &stamps).unwrap();
+                true
+            } else {
+                false
+            }
+        },
+    )
+}
@@ -13,6 +13,7 @@ mod edit;
 pub mod generate;
 mod geometry;
 mod immediate_mode;
+mod palette;
 mod picking;
 mod plugin;
 mod stress;
@@ -26,4 +27,5 @@ use picking::{VoxelCursor, VoxelCursorRayImpact, VoxelPickingPlugin};
 use plugin::EditorState;
 
 pub use config::*;
+pub use palette::Palette;
 pub use plugin::EditorPlugin;
@@ -0,0 +1,202 @@
+/*
+ * SPDX-License-Identifier: LGPL-3.0-or-later
+ */
+/*! Voxel types, shared by the editor, the generator, rendering, and stress analysis.
+ *
+ * The position of the entry in the file is the id of the voxel.
+ */
+
+use baustein::prefab::PaletteVoxel;
+use baustein::render::{Look, Looks, NO_LAYER};
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::Arc;
+
+
+/// The generator can't tell apart more voxel types than that.
+pub const MAX_ENTRIES: usize = 64;
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+pub struct Entry {
+    pub name: String,
+    /// Layer of the texture array. Empty voxels don't need one.
+    #[serde(default)]
+    pub layer: Option<u8>,
+    /// For places which don't show textures,
+    /// and when the texture array doesn't have the layer.
+    #[serde(default)]
+    pub colour: [f32; 3],
+    /// Not solid: doesn't get drawn and doesn't hold anything up.
+    #[serde(default)]
+    pub empty: bool,
+    /// Can be stood on.
+    #[serde(default)]
+    pub walkable: bool,
+    /// Holds structures up: cleanup keeps what touches it,
+    /// and stress analysis treats it as bedrock.
+    #[serde(default)]
+    pub ground: bool,
+}
+
+impl Entry {
+    pub fn to_look(&self) -> Look {
+        let [r, g, b] = self.colour;
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Look {
+            layer: self.layer.map(u32::from).unwrap_or(NO_LAYER),
+            colour: [channel(r), channel(g), channel(b), 255],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct File {
+    entries: Vec<Entry>,
+}
+
+/// The resource with all voxel types.
+/// Cheap to clone.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    entries: Arc<[Entry]>,
+}
+
+impl Palette {
+    pub fn read_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let file: File = ron::de::from_str(s)?;
+        Self::new(file.entries)
+    }
+
+    pub fn new(entries: Vec<Entry>) -> Result<Self, Box<dyn Error>> {
+        match entries.first() {
+            None => return Err("Palette has no entries".into()),
+            Some(entry) if !entry.empty => {
+                return Err(format!(
+                    "The first palette entry {:?} must be empty: it fills unset space",
+                    entry.name,
+                ).into());
+            },
+            _ => {},
+        };
+        if entries.len() > MAX_ENTRIES {
+            return Err(format!(
+                "Palette has {} entries, up to {} supported",
+                entries.len(),
+                MAX_ENTRIES,
+            ).into());
+        }
+        Ok(Self { entries: entries.into() })
+    }
+
+    pub fn get(&self, voxel: PaletteVoxel) -> Option<&Entry> {
+        self.entries.get(voxel.0 as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(PaletteVoxel, &Entry)> {
+        self.entries.iter()
+            .enumerate()
+            .map(|(id, entry)| (PaletteVoxel(id as u8), entry))
+    }
+
+    pub fn find(&self, name: &str) -> Option<PaletteVoxel> {
+        self.iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(voxel, _)| voxel)
+    }
+
+    /// Voxels missing from the palette are not solid.
+    pub fn is_solid(&self, voxel: PaletteVoxel) -> bool {
+        self.get(voxel).map(|entry| !entry.empty).unwrap_or(false)
+    }
+
+    pub fn is_ground(&self, voxel: PaletteVoxel) -> bool {
+        self.get(voxel).map(|entry| entry.ground).unwrap_or(false)
+    }
+
+    pub fn get_name(&self, voxel: PaletteVoxel) -> &str {
+        self.get(voxel).map(|entry| entry.name.as_str()).unwrap_or("?")
+    }
+
+    /// For the renderer.
+    pub fn to_looks(&self) -> Looks {
+        Looks(self.entries.iter().map(Entry::to_look).collect())
+    }
+}
+
+/// The palette matching the bundled textures.
+impl Default for Palette {
+    fn default() -> Self {
+        let entry = |name: &str, layer, colour| Entry {
+            name: name.into(),
+            layer: Some(layer),
+            colour,
+            empty: false,
+            walkable: true,
+            ground: false,
+        };
+        Self::new(vec![
+            Entry {
+                name: "Empty".into(),
+                layer: None,
+                colour: [0.0, 0.0, 0.0],
+                empty: true,
+                walkable: false,
+                ground: false,
+            },
+            Entry { ground: true, ..entry("Grass", 0, [0.3, 0.6, 0.2]) },
+            entry("Concrete", 1, [0.5, 0.5, 0.5]),
+            entry("Wood", 2, [0.6, 0.4, 0.2]),
+            Entry { walkable: false, ..entry("Glass", 3, [0.7, 0.8, 0.9]) },
+        ]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let palette = Palette::parse(r#"(
+            entries: [
+                (name: "Empty", empty: true),
+                (name: "Grass", layer: Some(0), ground: true),
+            ],
+        )"#).unwrap();
+        assert_eq!(palette.find("Grass"), Some(PaletteVoxel(1)));
+        assert!(palette.is_ground(PaletteVoxel(1)));
+        assert!(!palette.is_solid(PaletteVoxel::EMPTY));
+        assert!(!palette.is_solid(PaletteVoxel(2)));
+    }
+
+    #[test]
+    fn looks() {
+        let palette = Palette::parse(r#"(
+            entries: [
+                (name: "Empty", empty: true),
+                (name: "Marker", colour: (1.0, 0.0, 0.5)),
+                (name: "Grass", layer: Some(0)),
+            ],
+        )"#).unwrap();
+        let looks = palette.to_looks();
+        assert_eq!(looks.get(PaletteVoxel(1)), Look { layer: NO_LAYER, colour: [255, 0, 128, 255] });
+        assert_eq!(looks.get(PaletteVoxel(2)).layer, 0);
+    }
+
+    #[test]
+    fn first_empty() {
+        assert!(Palette::parse(r#"(
+            entries: [(name: "Grass", layer: Some(0))],
+        )"#).is_err());
+    }
+
+    #[test]
+    fn bundled() {
+        let palette = Palette::read_file("palette.ron").unwrap();
+        assert_eq!(palette, Palette::default());
+    }
+}
@@ -24,7 +24,7 @@ use crate::{
     edit,
     generate, open_voxel_database, save_map_to_db, BevyConfig, CameraConfig,
     Config, CursorPositionPlugin, ImmediateModePlugin,
-    VoxelPickingPlugin,
+    Palette, VoxelPickingPlugin,
 };
 
 
@@ -59,11 +59,12 @@ impl PluginGroup for BevyPlugins {
 
 pub struct EditorPlugin {
     config: Config,
+    palette: Palette,
 }
 
 impl EditorPlugin {
-    pub fn new(config: Config) -> Self {
-        Self { config }
+    pub fn new(config: Config, palette: Palette) -> Self {
+        Self { config, palette }
     }
 }
 
@@ -92,6 +93,7 @@ impl Plugin for EditorPlugin {
             .add_plugins(BevyPlugins::new(self.config.bevy))
             // Editor stuff.
             .insert_resource(self.config.clone())
+            .insert_resource(self.palette.clone())
             // Workaround. Something still needs it, no idea what does.
             .insert_resource(feldspar::bvt::VoxelBvt::default())
 
@@ -127,6 +129,8 @@ impl Plugin for EditorPlugin {
             )
             // Common to editor and generator
             .add_plugin(baustein::render::Plugin)
+            // Replaces the default one from baustein.
+            .insert_resource(self.palette.to_looks())
             // Editor
             .add_system_set(
                 SystemSet::on_update(EditorState::Editing)
@@ -134,6 +138,9 @@ impl Plugin for EditorPlugin {
             )
             .add_plugin(edit::Plugin)
             // Generator
+            .insert_resource(self.config.generator)
+            .insert_resource(self.config.scene.clone())
+            .insert_resource(self.config.postprocess.clone())
             .add_plugin(generate::Plugin)
             .add_plugin(generate::render::Plugin)
             // Nice but not used
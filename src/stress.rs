@@ -53,12 +53,15 @@
  */
 
 use baustein::indices::{Neighbours6, NamedNeighbours6};
-use baustein::traits::{ Cuboid as Extent, IterableSpace, Space };
+use baustein::prefab::PaletteVoxel;
+use baustein::traits::{ Cuboid as Extent, ExtentMismatch, IterableSpace, Space, ZipAll };
 use baustein::world::FlatPaddedCuboid;
 use float_ord::FloatOrd;
 use std::fmt;
 use std::ops;
 
+use crate::Palette;
+
 
 #[derive(Clone, Copy)]
 struct Mass(f32);
@@ -125,6 +128,19 @@ impl Default for StressVoxel {
     }
 }
 
+impl StressVoxel {
+    /// Ground holds everything, so it's the bedrock.
+    pub fn from_palette(palette: &Palette, voxel: PaletteVoxel) -> Self {
+        if palette.is_ground(voxel) {
+            StressVoxel::Bedrock
+        } else if palette.is_solid(voxel) {
+            StressVoxel::Bound
+        } else {
+            StressVoxel::Empty
+        }
+    }
+}
+
 /// Forces in the down direction
 type SixForces = Neighbours6<Force>;
 
@@ -188,16 +204,14 @@ where S: Space<Voxel=SixForces> + Extent + IterableSpace
 fn get_newton_global_loss<S>(space: &S) -> f32
     where S: Space<Voxel=ThreeForces> + IterableSpace
 {
-    let mut sum = 0.0;
-    space
-        .visit_indices(|i| {
-            sum += space.get(i)
-                .0
+    space.voxels()
+        .map(|(_, forces)| {
+            forces.0
                 .iter()
                 .map(|f| f.0 * f.0)
                 .sum::<f32>()
-        });
-    sum
+        })
+        .sum()
 }
 
 /// Initializes the outwardly forces array.
@@ -214,18 +228,21 @@ pub fn get_initial_forces<S>(voxels: &S) -> FlatPaddedCuboid<SixForces>
 /// `weights` is the space with unchanging weight forces acting on voxels.
 /// `forces` contains the current estimate of forces acting between voxels.
 /// The return value is the next estimate of forces between voxels.
+///
+/// Fails if the spaces don't cover the same voxels.
 pub fn distribute<FS, WS, VS>(space: &VS, weights: &WS, forces: &FS)
-    -> FlatPaddedCuboid<SixForces>
+    -> Result<FlatPaddedCuboid<SixForces>, ExtentMismatch>
 where
     FS: Space<Voxel=SixForces> + Extent + IterableSpace,
     VS: Space<Voxel=StressVoxel> + Extent + IterableSpace,
     WS: Space<Voxel=Force> + Extent + IterableSpace,
 {
-    let outward = space.zip(forces);
-    space
-        .zip(weights)
-        .map_index(|i, v| voxel::distribute_forces(&outward, i, v))
+    let all = ZipAll::try_new((space, weights, forces))?;
+    let outward = all.map(|(sv, _weight, sf)| (sv, sf));
+    Ok(all
+        .map_index(|i, (sv, weight, _sf)| voxel::distribute_forces(&outward, i, (sv, weight)))
         .into()
+    )
 }
 
 /// Convenience function for calculating how far from reaching the goal we are.
@@ -246,8 +263,10 @@ fn calculate_loss<FS>(forces: &FS) -> f32
 /// Detached pieces will carry nonsense results:
 /// 1x1x1 contributes 0 loss and experiences 0 strain,
 /// while bigger ones contributes to loss but carries no strain.
+///
+/// Fails if the weights don't cover the same voxels as the space.
 pub fn solve<'a, SF, SV>(weights: &'a SF, space: &'a SV, threshold: f32)
-    -> FlatPaddedCuboid<Stress>
+    -> Result<FlatPaddedCuboid<Stress>, ExtentMismatch>
 where
     SF: Space<Voxel=Force> + Extent + IterableSpace,
     SV: Space<Voxel=StressVoxel> + Extent + IterableSpace,
@@ -256,7 +275,7 @@ where
     // forces are zeroed at this stage.
     loop {
         // Insert some values into forces
-        forces = distribute(space, &weights, &forces);
+        forces = distribute(space, &weights, &forces)?;
         // Optional check for quality of the result.
         // Does not need to be done on each loop,
         // but it's needed to stop.
@@ -264,7 +283,7 @@ where
             // Overall divergence from Newton's laws. Closer to 0 is better.
             let overall = calculate_loss(&forces);
             if overall < threshold {
-                return forces.map(|sf| get_stress_sum(sf)).into()
+                return Ok(forces.map(|sf| get_stress_sum(sf)).into())
             }
         }
     }
@@ -408,6 +427,15 @@ mod test {
     use baustein::world::FlatPaddedGridCuboid;
     use baustein::re::ConstPow2Shape;
 
+    #[test]
+    fn palette() {
+        let palette = Palette::default();
+        let voxel = |name| StressVoxel::from_palette(&palette, palette.find(name).unwrap());
+        assert!(matches!(voxel("Empty"), StressVoxel::Empty));
+        assert!(matches!(voxel("Grass"), StressVoxel::Bedrock));
+        assert!(matches!(voxel("Wood"), StressVoxel::Bound));
+    }
+
 /*
     struct Solver(FlatPaddedCuboid<SixForces>);
 
@@ -431,7 +459,7 @@ mod test {
         let weights = world.map(|_v| Force(1.0));
         let outforces = get_initial_forces(&world);
 
-        let outforces = distribute(&world, &weights, &outforces);
+        let outforces = distribute(&world, &weights, &outforces).unwrap();
         
         let balance = process_newton_discrepancy(&outforces);
 
@@ -439,6 +467,16 @@ mod test {
         assert_eq!(balance.get([0, 0, 0].into()).imbalance().0, 0.0);
     }
 
+    #[test]
+    fn mismatched_weights() {
+        type Shape = ConstPow2Shape<1, 1, 1>;
+        let world = FlatPaddedGridCuboid::<StressVoxel, Shape>::new([0, 0, 0].into());
+        let elsewhere = FlatPaddedGridCuboid::<StressVoxel, Shape>::new([1, 0, 0].into());
+        let weights = elsewhere.map(|_v| Force(1.0));
+        let outforces = get_initial_forces(&world);
+        assert!(distribute(&world, &weights, &outforces).is_err());
+    }
+
     /// Checks a single bound voxel.
     /// Actually, this will never work. the forces have nowhere to spread.
     #[test]
@@ -452,7 +490,7 @@ mod test {
         let weights = world.map(|_v| Force(1.0));
         let outforces = get_initial_forces(&world);
 
-        let outforces = distribute(&world, &weights, &outforces);
+        let outforces = distribute(&world, &weights, &outforces).unwrap();
         
         let balance = process_newton_discrepancy(&outforces);
 
@@ -473,7 +511,7 @@ mod test {
         let mut outforces = get_initial_forces(&world);
 
         for i in 0..4 {
-            outforces = distribute(&world, &weights, &outforces);
+            outforces = distribute(&world, &weights, &outforces).unwrap();
             let balance = process_newton_discrepancy(&outforces);
             println!("i {} bound {}", i, balance.get([1, 1, 1].into()).imbalance().0);
             println!("bedrock {}", balance.get([1, 1, 2].into()).imbalance().0);
@@ -508,7 +546,7 @@ mod test {
         let mut outforces = get_initial_forces(&world);
 
         for i in 0..10 {
-            outforces = distribute(&world, &weights, &outforces);
+            outforces = distribute(&world, &weights, &outforces).unwrap();
             let balance = process_newton_discrepancy(&outforces);
             println!("i {} bedrock {}", i, balance.get([1, 1, 1].into()).imbalance().0);
             println!("bound {}", balance.get([1, 1, 2].into()).imbalance().0);